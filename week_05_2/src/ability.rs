@@ -0,0 +1,325 @@
+use traits::Position;
+use point::Point;
+use fov;
+use noise;
+use ai::{Target, TurnContext};
+use units::{AttackResult, Unit};
+use unit_type::{AbilityCondition, AbilityEffect};
+use ui::{MessageCategory, UI};
+
+use std::cmp::{min, Ordering};
+
+// Breath weapons hit tiles within about 45 degrees either side of the target.
+const BREATH_SPREAD: f64 = 0.7;
+
+// Tries each of the unit's abilities in order, using any that are ready.
+// Returns true if one of them used up the unit's turn.
+pub fn use_abilities<'a, 'b>(unit: &mut Unit<'a>, target: Option<Target>, ctx: &mut TurnContext<'a, 'b>) -> bool {
+    let was_hit = unit.take_was_hit();
+
+    for (idx, ability) in unit.get_unit_type().get_abilities().iter().enumerate() {
+        if unit.get_cooldown(idx) > 0 || !condition_met(unit, ability.condition, target, ctx) {
+            continue;
+        }
+
+        let used = match ability.effect {
+            AbilityEffect::PoisonBite{damage, turns} => poison_bite(unit, target, ctx, damage, turns),
+            AbilityEffect::LifeDrain{amount} => life_drain(unit, target, ctx, amount),
+            AbilityEffect::Summon{ref minion} => summon(unit, target, ctx, minion),
+            AbilityEffect::SplitOnHit => was_hit && split(unit, ctx),
+            AbilityEffect::Breath{damage, range} => breath(unit, target, ctx, damage, range),
+            AbilityEffect::Regenerate{amount} => regenerate(unit, ctx, amount),
+            AbilityEffect::Confuse{turns, range} => confuse(unit, ctx, turns, range),
+            AbilityEffect::Charm{turns, range} => charm(unit, ctx, turns, range),
+        };
+
+        if used {
+            unit.set_cooldown(idx, ability.cooldown);
+
+            // Passive abilities happen on top of whatever else the unit does.
+            match ability.effect {
+                AbilityEffect::SplitOnHit | AbilityEffect::Regenerate{..} => {},
+                _ => return true,
+            }
+        }
+    }
+
+    false
+}
+
+fn condition_met<'a, 'b>(unit: &Unit<'a>, condition: AbilityCondition, target: Option<Target>, ctx: &TurnContext<'a, 'b>) -> bool {
+    let distance = target.map(|t| (t.get_position(ctx) - unit.get_position()).radius());
+
+    match condition {
+        AbilityCondition::Always => true,
+        AbilityCondition::Adjacent => distance.is_some_and(|d| d < 2.0),
+        AbilityCondition::AtRange => distance.is_some_and(|d| d >= 2.0),
+        AbilityCondition::Hurt => (unit.get_hp() as u16) * 2 < unit.get_unit_type().get_max_hp() as u16,
+    }
+}
+
+// Whether the player can see anything happening at either position.
+fn is_seen<'a, 'b>(ctx: &TurnContext<'a, 'b>, a: Point<i16>, b: Point<i16>) -> bool {
+    let player_pos = ctx.player.get_position();
+    ctx.map.can_see_unit(a, player_pos) || ctx.map.can_see_unit(b, player_pos)
+}
+
+// The target, if it's next to the unit.
+fn adjacent_target<'a, 'b>(unit: &Unit<'a>, target: Option<Target>, ctx: &TurnContext<'a, 'b>) -> Option<Target> {
+    target.filter(|t| (t.get_position(ctx) - unit.get_position()).radius() < 2.0)
+}
+
+fn poison_bite<'a, 'b>(unit: &Unit<'a>, target: Option<Target>, ctx: &mut TurnContext<'a, 'b>, damage: u8, turns: u8) -> bool {
+    let target = match adjacent_target(unit, target, ctx) {
+        Some(target) => target,
+        None => return false,
+    };
+
+    let target_pos = target.get_position(ctx);
+    let seen = is_seen(ctx, unit.get_position(), target_pos);
+
+    let victim = match target {
+        Target::Player => &mut *ctx.player,
+        Target::Npc(idx) => &mut ctx.npcs[idx],
+    };
+
+    let result = unit.attack_with_verb(victim, if seen { Some(&mut *ctx.ui) } else { None }, "bites");
+
+    if result == AttackResult::Alive {
+        victim.poison(damage, turns);
+        if seen {
+            ctx.ui.add_message(&format!("{} is poisoned!", victim.get_name()), victim.get_damage_category());
+        }
+    }
+
+    ctx.map.make_noise(target_pos, noise::COMBAT_LOUDNESS);
+    true
+}
+
+fn life_drain<'a, 'b>(unit: &mut Unit<'a>, target: Option<Target>, ctx: &mut TurnContext<'a, 'b>, amount: u8) -> bool {
+    let target = match adjacent_target(unit, target, ctx) {
+        Some(target) => target,
+        None => return false,
+    };
+
+    let target_pos = target.get_position(ctx);
+    let seen = is_seen(ctx, unit.get_position(), target_pos);
+
+    let victim = match target {
+        Target::Player => &mut *ctx.player,
+        Target::Npc(idx) => &mut ctx.npcs[idx],
+    };
+
+    // Drained health ignores armour.
+    let drained = min(amount, victim.get_hp());
+    victim.alert(unit.get_position());
+    victim.take_damage(drained);
+    unit.heal(drained);
+
+    if seen {
+        ctx.ui.add_message(&format!("{} drains {} health from {}!", unit.get_name(), drained, victim.get_name()), victim.get_damage_category());
+    }
+
+    ctx.map.make_noise(target_pos, noise::COMBAT_LOUDNESS);
+    true
+}
+
+fn summon<'a, 'b>(unit: &Unit<'a>, target: Option<Target>, ctx: &mut TurnContext<'a, 'b>, minion: &str) -> bool {
+    let target_pos = match target {
+        Some(target) => target.get_position(ctx),
+        None => return false,
+    };
+
+    let pos = match ctx.map.get_free_neighbour(unit.get_position(), ctx.player.get_position()) {
+        Some(pos) => pos,
+        None => return false,
+    };
+
+    let state = unit.get_ai_state();
+    if ctx.spawner.spawn(ctx.map, minion, pos, state).is_none() {
+        return false;
+    }
+
+    if is_seen(ctx, unit.get_position(), target_pos) {
+        ctx.ui.add_message(&format!("{} summons a {}!", unit.get_name(), minion), MessageCategory::Combat);
+    }
+
+    true
+}
+
+// Splits the unit's health between itself and a new copy.
+fn split<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) -> bool {
+    let hp = unit.get_hp();
+    if hp < 2 {
+        return false;
+    }
+
+    let pos = match ctx.map.get_free_neighbour(unit.get_position(), ctx.player.get_position()) {
+        Some(pos) => pos,
+        None => return false,
+    };
+
+    let unit_type = unit.get_unit_type();
+    let state = unit.get_ai_state();
+    let copy_hp = hp / 2;
+
+    match ctx.spawner.spawn(ctx.map, unit_type.get_name(), pos, state) {
+        Some(copy) => {
+            copy.take_damage(unit_type.get_max_hp() - copy_hp);
+            copy.take_was_hit();
+        },
+        None => return false,
+    }
+    unit.take_damage(copy_hp);
+    // Splitting shouldn't count as being hit again.
+    unit.take_was_hit();
+
+    if is_seen(ctx, unit.get_position(), pos) {
+        ctx.ui.add_message(&format!("{} splits in two!", unit.get_name()), MessageCategory::Combat);
+    }
+
+    true
+}
+
+// Hurts everything in a cone from the unit towards its target, as long as
+// the target is in range.
+fn breath<'a, 'b>(unit: &Unit<'a>, target: Option<Target>, ctx: &mut TurnContext<'a, 'b>, damage: u8, range: u8) -> bool {
+    let pos = unit.get_position();
+    let target_pos = match target {
+        Some(target) => target.get_position(ctx),
+        None => return false,
+    };
+
+    if !ctx.map.has_line_of_sight(pos, target_pos, range) {
+        return false;
+    }
+
+    let aim = target_pos - pos;
+    let aim_len = aim.radius();
+    let cone: Vec<Point<i16>> = {
+        let map = &*ctx.map;
+        fov::compute_fov(pos, range, |p| map.blocks_sight(p)).into_iter()
+            .filter(|&p| p != pos)
+            .filter(|&p| {
+                let offset = p - pos;
+                let dot = offset.x as f64 * aim.x as f64 + offset.y as f64 * aim.y as f64;
+                dot / (offset.radius() * aim_len) >= BREATH_SPREAD
+            })
+            .collect()
+    };
+
+    if is_seen(ctx, pos, target_pos) {
+        ctx.ui.add_message(&format!("{} breathes fire!", unit.get_name()), MessageCategory::Combat);
+    }
+
+    if cone.contains(&ctx.player.get_position()) {
+        let seen = is_seen(ctx, pos, ctx.player.get_position());
+        burn(ctx.ui, unit, ctx.player, damage, seen);
+    }
+
+    for idx in 0..ctx.npcs.len() {
+        let victim_pos = ctx.npcs[idx].get_position();
+        if ctx.npcs[idx].get_hp() == 0 || !cone.contains(&victim_pos) {
+            continue;
+        }
+
+        let seen = is_seen(ctx, pos, victim_pos);
+        burn(ctx.ui, unit, &mut ctx.npcs[idx], damage, seen);
+    }
+
+    ctx.map.make_noise(target_pos, noise::COMBAT_LOUDNESS);
+    true
+}
+
+// Like an attack, the flames only hurt the breather's enemies, and armour
+// takes the edge off them.
+fn burn(ui: &mut UI, unit: &Unit, victim: &mut Unit, damage: u8, seen: bool) {
+    if !unit.is_hostile_to(victim) {
+        return;
+    }
+
+    victim.alert(unit.get_position());
+
+    match damage.checked_sub(victim.get_defence()) {
+        Some(damage) => {
+            victim.take_damage(damage);
+            if seen {
+                ui.add_message(&format!("{} is caught in the flames for {} damage.", victim.get_name(), damage), victim.get_damage_category());
+            }
+        },
+        None => {
+            if seen {
+                ui.add_message(&format!("{} is caught in the flames, but they have no effect!", victim.get_name()), MessageCategory::Combat);
+            }
+        },
+    }
+}
+
+// The nearest living NPC in sight and range that the unit is hostile to. The
+// player can't be picked, as they have no behaviour to replace, and nor can
+// anyone already charmed.
+fn find_npc_victim<'a, 'b>(unit: &Unit<'a>, ctx: &TurnContext<'a, 'b>, range: u8) -> Option<usize> {
+    let pos = unit.get_position();
+
+    let in_range: Vec<(usize, Point<i16>, f64)> = ctx.npcs.iter().enumerate()
+        .filter(|&(_, n)| n.get_hp() > 0 && !n.is_charmed() && unit.is_hostile_to(n))
+        .map(|(i, n)| (i, n.get_position(), (n.get_position() - pos).radius()))
+        .filter(|&(_, _, dist)| dist <= range as f64)
+        .collect();
+
+    if in_range.is_empty() {
+        return None;
+    }
+
+    let visible = ctx.map.get_visible_from(pos, range);
+    in_range.into_iter()
+        .filter(|&(_, p, _)| visible.contains(&p))
+        .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal))
+        .map(|(i, _, _)| i)
+}
+
+fn confuse<'a, 'b>(unit: &Unit<'a>, ctx: &mut TurnContext<'a, 'b>, turns: u8, range: u8) -> bool {
+    let idx = match find_npc_victim(unit, ctx, range) {
+        Some(idx) => idx,
+        None => return false,
+    };
+
+    let seen = is_seen(ctx, unit.get_position(), ctx.npcs[idx].get_position());
+    let victim = &mut ctx.npcs[idx];
+    victim.confuse(turns);
+
+    if seen {
+        ctx.ui.add_message(&format!("{} confuses {}!", unit.get_name(), victim.get_name()), MessageCategory::Combat);
+    }
+    true
+}
+
+fn charm<'a, 'b>(unit: &Unit<'a>, ctx: &mut TurnContext<'a, 'b>, turns: u8, range: u8) -> bool {
+    let idx = match find_npc_victim(unit, ctx, range) {
+        Some(idx) => idx,
+        None => return false,
+    };
+
+    let seen = is_seen(ctx, unit.get_position(), ctx.npcs[idx].get_position());
+    let victim = &mut ctx.npcs[idx];
+    victim.charm(unit.get_unit_type(), turns);
+
+    if seen {
+        ctx.ui.add_message(&format!("{} charms {}!", unit.get_name(), victim.get_name()), MessageCategory::Combat);
+    }
+    true
+}
+
+fn regenerate<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>, amount: u8) -> bool {
+    if unit.get_hp() >= unit.get_unit_type().get_max_hp() {
+        return false;
+    }
+
+    unit.heal(amount);
+
+    if ctx.map.can_see_unit(unit.get_position(), ctx.player.get_position()) {
+        ctx.ui.add_message(&format!("{}'s wounds close up.", unit.get_name()), MessageCategory::Flavour);
+    }
+
+    true
+}
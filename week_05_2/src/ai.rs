@@ -0,0 +1,676 @@
+use rand;
+use rand::Rng;
+
+use traits::Position;
+use Direction;
+use point::Point;
+use map;
+use map::Map;
+use noise;
+use ability;
+use ui::UI;
+use units::{AiState, Unit};
+use unit_type::{AiKind, UnitTypeLists};
+
+use std;
+use std::cmp::Ordering;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Debug;
+
+// Pack hunters wait until enough of their pack is close by before attacking.
+const PACK_RADIUS: f64 = 6.0;
+const PACK_MIN_ALLIES: usize = 2;
+// Pack members are willing to take a longer way round to reach an open side
+// of the player, instead of queuing up behind each other.
+const PACK_PATH_LIMIT: i32 = 40;
+// Units that hang back try to keep about this far away from the player.
+const HOLD_BACK_DISTANCE: f64 = 4.0;
+// How close the player can get before a coward runs.
+const COWARD_DISTANCE: f64 = 5.0;
+// Allies don't crowd the player unless there's a fight.
+const FOLLOW_DISTANCE: f64 = 3.0;
+// How far a unit will go out of its way to reach a monster it's fighting.
+const ENEMY_PATH_LIMIT: i32 = 25;
+
+// Everything a unit might need to look at or change while taking its turn.
+pub struct TurnContext<'a: 'b, 'b> {
+    pub map: &'b mut Map,
+    pub ui: &'b mut UI,
+    // Every NPC except the one taking its turn.
+    pub npcs: &'b mut VecDeque<Unit<'a>>,
+    pub player: &'b mut Unit<'a>,
+    pub spawner: &'b mut Spawner<'a>,
+    // Tiles next to the player that a pack member has already decided to
+    // head for this turn.
+    pub claimed_tiles: &'b mut HashSet<Point<i16>>,
+}
+
+// Collects the units created during the NPCs' turns. They can't be added to
+// the NPC list straight away, as it's borrowed while the turns are taken.
+pub struct Spawner<'a> {
+    unit_types: &'a UnitTypeLists,
+    spawned: Vec<Unit<'a>>,
+}
+
+impl<'a> Spawner<'a> {
+    pub fn new(unit_types: &'a UnitTypeLists) -> Spawner<'a> {
+        Spawner {
+            unit_types: unit_types,
+            spawned: vec![],
+        }
+    }
+
+    pub fn spawn(&mut self, map: &mut Map, type_name: &str, pos: Point<i16>, state: AiState) -> Option<&mut Unit<'a>> {
+        let unit_type = self.unit_types.get_type_by_name(type_name)?;
+        let mut unit = Unit::new(pos, unit_type);
+        unit.set_ai_state(state);
+        map.add_occupant(&unit);
+        self.spawned.push(unit);
+        self.spawned.last_mut()
+    }
+
+    pub fn take_spawned(&mut self) -> Vec<Unit<'a>> {
+        std::mem::take(&mut self.spawned)
+    }
+}
+
+// Who a unit is fighting.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Target {
+    Player,
+    // An index into the NPC list.
+    Npc(usize),
+}
+
+impl Target {
+    pub fn get_position<'a, 'b>(&self, ctx: &TurnContext<'a, 'b>) -> Point<i16> {
+        match *self {
+            Target::Player => ctx.player.get_position(),
+            Target::Npc(idx) => ctx.npcs[idx].get_position(),
+        }
+    }
+}
+
+pub trait AiBehaviour<'a>: Debug {
+    // What to do once the unit has found the player.
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>);
+
+    // What to do about a unit from a hostile faction, given by its index in
+    // the NPC list.
+    fn fight<'b>(&mut self, unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>) {
+        let can_move = self.can_move();
+        melee(unit, target, ctx, can_move);
+    }
+
+    fn can_move(&self) -> bool {
+        true
+    }
+
+    fn wants_to_flee(&self, unit: &Unit<'a>, _player_pos: Point<i16>) -> bool {
+        unit.is_badly_hurt()
+    }
+
+    // Temporary behaviours return the one they replaced once they wear off.
+    fn wear_off(&mut self, _unit: &mut Unit<'a>) -> Option<Box<dyn AiBehaviour<'a> + 'a>> {
+        None
+    }
+
+    fn take_turn<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        let player_pos = ctx.player.get_position();
+        let sees_player = ctx.player.get_hp() > 0 && unit.is_hostile_to(ctx.player) && unit.can_see(ctx.map, player_pos);
+        let wants_to_flee = unit.is_morale_broken() || self.wants_to_flee(unit, player_pos);
+        let heard = ctx.map.get_heard_noise(unit.get_position());
+
+        unit.update_ai_state(ctx.map, ctx.ui, sees_player, wants_to_flee, player_pos, heard);
+
+        let state = unit.get_ai_state();
+        let enemy = match state {
+            AiState::Asleep | AiState::Fleeing => None,
+            _ => find_enemy(unit, ctx),
+        };
+
+        let target = match (state, enemy) {
+            (AiState::Hunting(_), Some((_, dist))) if dist >= distance_to_player(unit, ctx) => Some(Target::Player),
+            (_, Some((idx, _))) => Some(Target::Npc(idx)),
+            (AiState::Hunting(_), None) => Some(Target::Player),
+            _ => None,
+        };
+
+        if state != AiState::Asleep && ability::use_abilities(unit, target, ctx) {
+            return;
+        }
+
+        match (state, enemy) {
+            (AiState::Asleep, _) => {},
+            (AiState::Fleeing, _) => {
+                // A cornered unit will fight instead.
+                match unit.get_step_away_from_player(ctx.map) {
+                    Some(new_pos) if self.can_move() => unit.walk_to(ctx.map, new_pos),
+                    _ => self.hunt(unit, ctx),
+                }
+            },
+            // The player comes first, unless something else is closer.
+            (AiState::Hunting(_), Some((_, dist))) if dist >= distance_to_player(unit, ctx) => self.hunt(unit, ctx),
+            // Allies told to stay only fight what comes to them.
+            (AiState::Staying(_), Some((target, _))) => melee(unit, target, ctx, false),
+            (_, Some((target, _))) => self.fight(unit, target, ctx),
+            (AiState::Following, None) => {
+                if self.can_move() {
+                    follow(unit, ctx);
+                }
+            },
+            (AiState::Staying(dest), None) => {
+                if self.can_move() && dest != unit.get_position() {
+                    unit.travel_towards(ctx.map, dest);
+                }
+            },
+            (AiState::Wandering(dest), None) | (AiState::Investigating(dest), None) => {
+                if self.can_move() {
+                    unit.travel_towards(ctx.map, dest);
+                }
+            },
+            (AiState::Hunting(_), None) => self.hunt(unit, ctx),
+        }
+    }
+}
+
+pub fn new_behaviour<'a>(kind: &AiKind) -> Box<dyn AiBehaviour<'a> + 'a> {
+    match *kind {
+        AiKind::MeleeBrute => Box::new(MeleeBrute),
+        AiKind::RangedKiter{range} => Box::new(RangedKiter{ range: range }),
+        AiKind::Coward => Box::new(Coward),
+        AiKind::PackHunter => Box::new(PackHunter),
+        AiKind::StationaryTurret => Box::new(StationaryTurret),
+        AiKind::Summoner => Box::new(Summoner),
+    }
+}
+
+fn distance_to_player<'a, 'b>(unit: &Unit<'a>, ctx: &TurnContext<'a, 'b>) -> f64 {
+    (unit.get_position() - ctx.player.get_position()).radius()
+}
+
+// Finds the nearest living NPC from a hostile faction that the unit can see,
+// returning its index in the NPC list and how far away it is.
+fn find_enemy<'a, 'b>(unit: &Unit<'a>, ctx: &TurnContext<'a, 'b>) -> Option<(usize, f64)> {
+    let pos = unit.get_position();
+    let sight_radius = unit.get_unit_type().get_sight_radius();
+
+    // Only enemies in range are worth a field of view check, and most of the
+    // time there aren't any.
+    let in_range: Vec<(usize, Point<i16>, f64)> = ctx.npcs.iter().enumerate()
+        .filter(|&(_, n)| n.get_hp() > 0 && unit.is_hostile_to(n))
+        .map(|(i, n)| (i, n.get_position(), (n.get_position() - pos).radius()))
+        .filter(|&(_, _, dist)| dist <= sight_radius as f64)
+        .collect();
+
+    if in_range.is_empty() {
+        return None;
+    }
+
+    // Units always notice things right next to them, even when blind.
+    let visible = ctx.map.get_visible_from(pos, sight_radius);
+    in_range.into_iter()
+        .filter(|&(_, p, dist)| dist < 2.0 || visible.contains(&p))
+        .map(|(i, _, dist)| (i, dist))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+}
+
+// Attacks another NPC. Fights are only reported if the player can see them.
+fn strike<'a, 'b>(unit: &Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>, ranged: bool) {
+    let player_pos = ctx.player.get_position();
+    let target_pos = ctx.npcs[target].get_position();
+    let seen = ctx.map.can_see_unit(unit.get_position(), player_pos) || ctx.map.can_see_unit(target_pos, player_pos);
+
+    let victim = &mut ctx.npcs[target];
+    match (seen, ranged) {
+        (true, false) => unit.attack(victim, ctx.ui),
+        (true, true) => unit.shoot(victim, ctx.ui),
+        (false, false) => unit.attack_unseen(victim),
+        (false, true) => unit.shoot_unseen(victim),
+    };
+
+    ctx.map.make_noise(target_pos, noise::COMBAT_LOUDNESS);
+}
+
+// Moves towards another NPC, attacking once next to it.
+fn melee<'a, 'b>(unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>, can_move: bool) {
+    let pos = unit.get_position();
+    let target_pos = ctx.npcs[target].get_position();
+
+    if (target_pos - pos).radius() < 2.0 {
+        strike(unit, target, ctx, false);
+    } else if can_move {
+        if let Some(new_pos) = ctx.map.get_path_grid().find_step(pos, target_pos, ENEMY_PATH_LIMIT) {
+            unit.walk_to(ctx.map, new_pos);
+        }
+    }
+}
+
+// Shoots another NPC if it's in range, returning false if it isn't.
+fn try_shoot_npc<'a, 'b>(unit: &Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>, range: u8) -> bool {
+    if ctx.map.has_line_of_sight(unit.get_position(), ctx.npcs[target].get_position(), range) {
+        strike(unit, target, ctx, true);
+        true
+    } else {
+        false
+    }
+}
+
+// Keeps an ally close to the player.
+fn follow<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+    if distance_to_player(unit, ctx) > FOLLOW_DISTANCE {
+        if let Some(new_pos) = unit.get_step_towards_player(ctx.map, ctx.player.get_position()) {
+            unit.walk_to(ctx.map, new_pos);
+        }
+    }
+}
+
+// Moves towards the player, attacking once next to them.
+fn charge<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+    let player_pos = ctx.player.get_position();
+
+    if distance_to_player(unit, ctx) >= 2.0 {
+        if let Some(new_pos) = unit.get_step_towards_player(ctx.map, player_pos) {
+            if ctx.map.can_move_to(new_pos) == map::CanMoveResponse::Open {
+                unit.walk_to(ctx.map, new_pos);
+            }
+        }
+    } else if ctx.player.get_hp() > 0 {
+        unit.attack(ctx.player, ctx.ui);
+        ctx.map.make_noise(player_pos, noise::COMBAT_LOUDNESS);
+    }
+}
+
+// Stays near the player without getting too close. Units next to the
+// player fight back.
+fn hold_back<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+    let dist = distance_to_player(unit, ctx);
+
+    if dist < 2.0 {
+        charge(unit, ctx);
+    } else if dist < HOLD_BACK_DISTANCE {
+        if let Some(new_pos) = unit.get_step_away_from_player(ctx.map) {
+            unit.walk_to(ctx.map, new_pos);
+        }
+    } else if dist > HOLD_BACK_DISTANCE + 2.0 {
+        charge(unit, ctx);
+    }
+}
+
+// Returns false if the player is out of range or out of sight.
+fn try_shoot<'a, 'b>(unit: &Unit<'a>, ctx: &mut TurnContext<'a, 'b>, range: u8) -> bool {
+    let player_pos = ctx.player.get_position();
+
+    if ctx.player.get_hp() > 0 && ctx.map.has_line_of_sight(unit.get_position(), player_pos, range) {
+        unit.shoot(ctx.player, ctx.ui);
+        ctx.map.make_noise(player_pos, noise::COMBAT_LOUDNESS);
+        true
+    } else {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct MeleeBrute;
+
+impl<'a> AiBehaviour<'a> for MeleeBrute {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        charge(unit, ctx);
+    }
+}
+
+// Backs away when the player gets close, and shoots from a distance.
+#[derive(Debug)]
+pub struct RangedKiter {
+    range: u8,
+}
+
+impl<'a> AiBehaviour<'a> for RangedKiter {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        let dist = distance_to_player(unit, ctx);
+
+        if (2.0..HOLD_BACK_DISTANCE).contains(&dist) {
+            if let Some(new_pos) = unit.get_step_away_from_player(ctx.map) {
+                unit.walk_to(ctx.map, new_pos);
+                return;
+            }
+        }
+
+        let range = self.range;
+        if dist < 2.0 || !try_shoot(unit, ctx, range) {
+            charge(unit, ctx);
+        }
+    }
+
+    fn fight<'b>(&mut self, unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>) {
+        let range = self.range;
+        let adjacent = (ctx.npcs[target].get_position() - unit.get_position()).radius() < 2.0;
+
+        if adjacent || !try_shoot_npc(unit, target, ctx, range) {
+            melee(unit, target, ctx, true);
+        }
+    }
+}
+
+// Runs from the player on sight, and only fights when cornered.
+#[derive(Debug)]
+pub struct Coward;
+
+impl<'a> AiBehaviour<'a> for Coward {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        if distance_to_player(unit, ctx) < 2.0 {
+            charge(unit, ctx);
+        }
+    }
+
+    fn fight<'b>(&mut self, unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>) {
+        melee(unit, target, ctx, false);
+    }
+
+    fn wants_to_flee(&self, unit: &Unit<'a>, player_pos: Point<i16>) -> bool {
+        unit.is_badly_hurt() || (unit.get_position() - player_pos).radius() < COWARD_DISTANCE
+    }
+}
+
+// Moves towards a free tile next to the player that no other pack member is
+// heading for, so that the pack surrounds the player.
+fn surround<'a, 'b>(unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+    let pos = unit.get_position();
+    let player_pos = ctx.player.get_position();
+
+    let mut slots = vec![];
+    for y in -1..2 {
+        for x in -1..2 {
+            let slot = player_pos + Point{x: x, y: y};
+            if slot != player_pos && !ctx.claimed_tiles.contains(&slot) && ctx.map.get_path_grid().is_walkable(slot) {
+                slots.push(slot);
+            }
+        }
+    }
+
+    slots.sort_by_key(|&s| {
+        let Point{x, y} = s - pos;
+        x as i32 * x as i32 + y as i32 * y as i32
+    });
+
+    for slot in slots {
+        if let Some(step) = ctx.map.get_path_grid().find_step(pos, slot, PACK_PATH_LIMIT) {
+            ctx.claimed_tiles.insert(slot);
+            unit.walk_to(ctx.map, step);
+            return;
+        }
+    }
+
+    // Every side is taken or out of reach, so just get as close as possible.
+    charge(unit, ctx);
+}
+
+// Keeps its distance until enough of the pack has gathered, then surrounds
+// the player.
+#[derive(Debug)]
+pub struct PackHunter;
+
+impl<'a> AiBehaviour<'a> for PackHunter {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        let pos = unit.get_position();
+
+        // Units that weren't spawned in a pack will group up with their own kind.
+        let allies = ctx.npcs.iter()
+            .filter(|n| n.get_hp() > 0)
+            .filter(|n| match unit.get_pack() {
+                Some(pack) => n.get_pack() == Some(pack),
+                None => n.get_name() == unit.get_name(),
+            })
+            .filter(|n| (n.get_position() - pos).radius() <= PACK_RADIUS)
+            .count();
+
+        if distance_to_player(unit, ctx) < 2.0 {
+            charge(unit, ctx);
+        } else if allies >= PACK_MIN_ALLIES {
+            surround(unit, ctx);
+        } else {
+            hold_back(unit, ctx);
+        }
+    }
+}
+
+// Never moves, but shoots anything it can see.
+#[derive(Debug)]
+pub struct StationaryTurret;
+
+impl<'a> AiBehaviour<'a> for StationaryTurret {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        let range = unit.get_unit_type().get_sight_radius();
+        try_shoot(unit, ctx, range);
+    }
+
+    fn fight<'b>(&mut self, unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>) {
+        let range = unit.get_unit_type().get_sight_radius();
+        try_shoot_npc(unit, target, ctx, range);
+    }
+
+    fn can_move(&self) -> bool {
+        false
+    }
+
+    fn wants_to_flee(&self, _unit: &Unit<'a>, _player_pos: Point<i16>) -> bool {
+        false
+    }
+}
+
+// Stays out of the way while its Summon ability recharges, so its minions
+// do the fighting.
+#[derive(Debug)]
+pub struct Summoner;
+
+impl<'a> AiBehaviour<'a> for Summoner {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        hold_back(unit, ctx);
+    }
+
+    fn fight<'b>(&mut self, unit: &mut Unit<'a>, target: usize, ctx: &mut TurnContext<'a, 'b>) {
+        melee(unit, target, ctx, false);
+    }
+}
+
+// Stumbles around at random, hitting whatever it bumps into, then goes back
+// to what it was doing.
+#[derive(Debug)]
+pub struct Confused<'a> {
+    turns_left: u8,
+    previous: Option<Box<dyn AiBehaviour<'a> + 'a>>,
+}
+
+impl<'a> Confused<'a> {
+    pub fn new(turns: u8, previous: Box<dyn AiBehaviour<'a> + 'a>) -> Confused<'a> {
+        Confused {
+            turns_left: turns,
+            previous: Some(previous),
+        }
+    }
+}
+
+impl<'a> AiBehaviour<'a> for Confused<'a> {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        let mut rng = rand::thread_rng();
+        let dirs = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+        let dir = *rng.choose(&dirs).expect("No directions to choose from.");
+        let new_pos = unit.get_position() + dir.to_rel_point();
+
+        if new_pos == ctx.player.get_position() {
+            if ctx.player.get_hp() > 0 {
+                unit.attack(ctx.player, ctx.ui);
+                ctx.map.make_noise(new_pos, noise::COMBAT_LOUDNESS);
+            }
+        } else if let Some(target) = ctx.npcs.iter().position(|n| n.get_position() == new_pos && n.get_hp() > 0) {
+            strike(unit, target, ctx, false);
+        } else if ctx.map.get_path_grid().is_walkable(new_pos) {
+            unit.walk_to(ctx.map, new_pos);
+        }
+    }
+
+    fn take_turn<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        self.hunt(unit, ctx);
+        self.turns_left = self.turns_left.saturating_sub(1);
+    }
+
+    fn wear_off(&mut self, _unit: &mut Unit<'a>) -> Option<Box<dyn AiBehaviour<'a> + 'a>> {
+        if self.turns_left == 0 {
+            self.previous.take()
+        } else {
+            None
+        }
+    }
+}
+
+// Goes about things the same way as before, but on the charmer's side, then
+// goes back to what it was doing.
+#[derive(Debug)]
+pub struct Charmed<'a> {
+    turns_left: u8,
+    previous: Option<Box<dyn AiBehaviour<'a> + 'a>>,
+    previous_state: AiState,
+}
+
+impl<'a> Charmed<'a> {
+    pub fn new(turns: u8, previous: Box<dyn AiBehaviour<'a> + 'a>, state: AiState) -> Charmed<'a> {
+        Charmed {
+            turns_left: turns,
+            previous: Some(previous),
+            previous_state: state,
+        }
+    }
+}
+
+impl<'a> AiBehaviour<'a> for Charmed<'a> {
+    fn hunt<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        if let Some(ref mut previous) = self.previous {
+            previous.hunt(unit, ctx);
+        }
+    }
+
+    fn take_turn<'b>(&mut self, unit: &mut Unit<'a>, ctx: &mut TurnContext<'a, 'b>) {
+        if let Some(ref mut previous) = self.previous {
+            previous.take_turn(unit, ctx);
+        }
+        self.turns_left = self.turns_left.saturating_sub(1);
+    }
+
+    fn wear_off(&mut self, unit: &mut Unit<'a>) -> Option<Box<dyn AiBehaviour<'a> + 'a>> {
+        if self.turns_left == 0 {
+            unit.end_charm(self.previous_state);
+            self.previous.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcod;
+    use theme;
+    use unit_type;
+    use unit_type::UnitType;
+
+    // Takes every NPC's turn the given number of times, as the main loop does.
+    fn run_turns<'a>(map: &mut Map, npcs: &mut VecDeque<Unit<'a>>, player: &mut Unit<'a>, unit_types: &'a UnitTypeLists, turns: usize) {
+        let mut ui = UI::new(Point{x: 0, y: 0}, 80, 7, 10, theme::load_theme("default"));
+        let mut spawner = Spawner::new(unit_types);
+
+        for _ in 0..turns {
+            let mut claimed_tiles = HashSet::new();
+
+            for _ in 0..npcs.len() {
+                let mut npc = npcs.pop_front().unwrap();
+                npc.take_turn(&mut TurnContext {
+                    map: map,
+                    ui: &mut ui,
+                    npcs: npcs,
+                    player: player,
+                    spawner: &mut spawner,
+                    claimed_tiles: &mut claimed_tiles,
+                });
+                npcs.push_back(npc);
+            }
+        }
+    }
+
+    fn add_npc<'a>(map: &mut Map, npcs: &mut VecDeque<Unit<'a>>, unit_types: &'a UnitTypeLists, name: &str, pos: Point<i16>, state: AiState) {
+        let mut unit = Unit::new(pos, unit_types.get_type_by_name(name).unwrap());
+        unit.set_ai_state(state);
+        map.add_occupant(&unit);
+        npcs.push_back(unit);
+    }
+
+    fn find_npc<'a, 'b>(npcs: &'b VecDeque<Unit<'a>>, name: &str) -> &'b Unit<'a> {
+        npcs.iter().find(|n| n.get_name() == name).unwrap()
+    }
+
+    #[test]
+    fn hostile_units_close_in_on_each_other() {
+        let unit_types = unit_type::load_unit_types();
+        let player_type = UnitType::new("Player", '@', tcod::colors::WHITE);
+        // Far enough away that neither of them notices the player.
+        let mut player = Unit::new(Point{x: 38, y: 18}, &player_type);
+        let mut map = Map::new_test_map(40, 20);
+        let mut npcs = VecDeque::new();
+
+        add_npc(&mut map, &mut npcs, &unit_types, "Human", Point{x: 5, y: 5}, AiState::Investigating(Point{x: 10, y: 5}));
+        add_npc(&mut map, &mut npcs, &unit_types, "Orc", Point{x: 10, y: 5}, AiState::Investigating(Point{x: 5, y: 5}));
+
+        run_turns(&mut map, &mut npcs, &mut player, &unit_types, 2);
+
+        let gap = find_npc(&npcs, "Human").get_position() - find_npc(&npcs, "Orc").get_position();
+        assert!(gap.radius() < 2.0, "still {:?} apart", gap);
+    }
+
+    // Sets up a dog with the given orders, with a sleeping human for it to
+    // notice, then moves the human out of sight after the first turn.
+    fn lose_sight_of_enemy<'a>(map: &mut Map, npcs: &mut VecDeque<Unit<'a>>, player: &mut Unit<'a>, unit_types: &'a UnitTypeLists, dog_pos: Point<i16>, state: AiState) -> Point<i16> {
+        add_npc(map, npcs, unit_types, "Dog", dog_pos, state);
+        add_npc(map, npcs, unit_types, "Human", Point{x: 9, y: 10}, AiState::Asleep);
+        run_turns(map, npcs, player, unit_types, 1);
+
+        let human = npcs.iter_mut().find(|n| n.get_name() == "Human").unwrap();
+        human.walk_to(map, Point{x: 35, y: 3});
+        find_npc(npcs, "Dog").get_position()
+    }
+
+    #[test]
+    fn following_allies_come_back_once_the_enemy_is_gone() {
+        let unit_types = unit_type::load_unit_types();
+        let player_type = UnitType::new("Player", '@', tcod::colors::WHITE);
+        let mut player = Unit::new(Point{x: 2, y: 10}, &player_type);
+        let mut map = Map::new_test_map(40, 20);
+        let mut npcs = VecDeque::new();
+
+        let chased_to = lose_sight_of_enemy(&mut map, &mut npcs, &mut player, &unit_types, Point{x: 5, y: 10}, AiState::Following);
+        assert_eq!(chased_to, Point{x: 6, y: 10});
+
+        run_turns(&mut map, &mut npcs, &mut player, &unit_types, 2);
+        let dog = find_npc(&npcs, "Dog");
+        assert_eq!(dog.get_ai_state(), AiState::Following);
+        assert_eq!(dog.get_position(), Point{x: 5, y: 10});
+    }
+
+    #[test]
+    fn staying_allies_hold_their_post_once_the_enemy_is_gone() {
+        let unit_types = unit_type::load_unit_types();
+        let player_type = UnitType::new("Player", '@', tcod::colors::WHITE);
+        let mut player = Unit::new(Point{x: 2, y: 10}, &player_type);
+        let mut map = Map::new_test_map(40, 20);
+        let mut npcs = VecDeque::new();
+        let post = Point{x: 5, y: 10};
+
+        // Only fights what comes to it, so doesn't move towards the human.
+        let held = lose_sight_of_enemy(&mut map, &mut npcs, &mut player, &unit_types, post, AiState::Staying(post));
+        assert_eq!(held, post);
+
+        run_turns(&mut map, &mut npcs, &mut player, &unit_types, 2);
+        let dog = find_npc(&npcs, "Dog");
+        assert_eq!(dog.get_ai_state(), AiState::Staying(post));
+        assert_eq!(dog.get_position(), post);
+    }
+}
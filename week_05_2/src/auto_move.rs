@@ -0,0 +1,90 @@
+use traits::Position;
+use point::Point;
+use map::{ExploreStep, Map};
+use units::Unit;
+use ui::{MessageCategory, UI};
+
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Destination {
+    Unexplored,
+    Point(Point<i16>),
+}
+
+// Moves the player over several turns, stopping as soon as anything
+// interesting happens.
+#[derive(Debug)]
+pub struct AutoMove {
+    destination: Destination,
+    last_hp: u8,
+    // The ids of items already in view, so only new ones stop the move.
+    items_seen: HashSet<usize>,
+    // The ids of hostile units already in view. Only new ones stop the move,
+    // so it can be started with a monster in sight.
+    hostiles_seen: HashSet<usize>,
+}
+
+impl AutoMove {
+    pub fn new<'a>(destination: Destination, map: &Map, npcs: &VecDeque<Unit<'a>>, player: &Unit) -> AutoMove {
+        AutoMove {
+            destination: destination,
+            last_hp: player.get_hp(),
+            items_seen: map.get_visible_item_ids(),
+            hostiles_seen: get_visible_hostiles(map, npcs, player).iter().map(|n| n.get_id()).collect(),
+        }
+    }
+
+    // Returns None if the destination has been reached, or the move was interrupted.
+    pub fn next_step<'a>(&mut self, map: &Map, ui: &mut UI, npcs: &VecDeque<Unit<'a>>, player: &Unit) -> Option<Point<i16>> {
+        let pos = player.get_position();
+
+        if let Some(npc) = get_visible_hostiles(map, npcs, player).into_iter().find(|n| !self.hostiles_seen.contains(&n.get_id())) {
+            ui.add_message(&format!("You see a {}.", npc.get_name()), MessageCategory::System);
+            return None;
+        }
+
+        if player.get_hp() < self.last_hp {
+            ui.add_message("You've been hurt!", MessageCategory::DamageTaken);
+            return None;
+        }
+
+        let items_seen = map.get_visible_item_ids();
+        if !items_seen.is_subset(&self.items_seen) {
+            ui.add_message("You spot something.", MessageCategory::Loot);
+            return None;
+        }
+
+        self.last_hp = player.get_hp();
+
+        match self.destination {
+            Destination::Unexplored => {
+                match map.get_explore_step(pos) {
+                    ExploreStep::Step(step) => Some(step),
+                    ExploreStep::Blocked => {
+                        ui.add_message("Something is blocking the way.", MessageCategory::System);
+                        None
+                    },
+                    ExploreStep::Finished => {
+                        ui.add_message("There's nowhere left to explore.", MessageCategory::System);
+                        None
+                    },
+                }
+            },
+            Destination::Point(dest) if dest == pos => None,
+            Destination::Point(dest) => {
+                // Travel only goes through places the player knows about.
+                let step = map.get_path_grid().find_step_within(pos, dest, i32::MAX, |p| map.is_explored(p));
+                if step.is_none() {
+                    ui.add_message("You can't find a way there.", MessageCategory::System);
+                }
+                step
+            },
+        }
+    }
+}
+
+fn get_visible_hostiles<'a, 'b>(map: &Map, npcs: &'b VecDeque<Unit<'a>>, player: &Unit) -> Vec<&'b Unit<'a>> {
+    let pos = player.get_position();
+    npcs.iter().filter(|n| n.get_hp() > 0 && n.is_hostile_to(player) && map.can_see_unit(n.get_position(), pos)).collect()
+}
@@ -0,0 +1,38 @@
+use units::Unit;
+use morgue::RunStats;
+
+// The lines of the character screen.
+pub fn get_character_sheet(name: &str, player: &Unit, stats: &RunStats, sneaking: bool) -> Vec<String> {
+    let unit_type = player.get_unit_type();
+
+    let mut lines = vec![
+        format!("Name:     {}", name),
+        format!("Level:    {}", player.get_level()),
+        format!("XP:       {} ({} for the next level)", player.get_xp(), player.get_next_level_xp()),
+        String::new(),
+        format!("HP:       {}/{}", player.get_hp(), unit_type.get_max_hp()),
+        format!("Attack:   {}", player.get_attack()),
+        format!("Defence:  {}", player.get_defence()),
+        String::new(),
+        "Status:".into(),
+    ];
+
+    let mut effects = player.get_status_effects();
+    if sneaking {
+        effects.push("Sneaking".into());
+    }
+    if effects.is_empty() {
+        effects.push("None".into());
+    }
+    lines.extend(effects.into_iter().map(|e| format!("    {}", e)));
+
+    // There's nothing to equip yet, but this is where it'll be listed.
+    lines.push(String::new());
+    lines.push("Equipment:".into());
+    lines.push("    Nothing".into());
+
+    lines.push(String::new());
+    lines.push(format!("Kills:    {}", stats.get_kill_count()));
+
+    lines
+}
@@ -0,0 +1,143 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use point::Point;
+
+// Distances are scaled up so that inverted maps can be multiplied by a
+// fractional amount without losing precision.
+const STEP_COST: i32 = 10;
+// How much an inverted map favours getting further away over avoiding dead
+// ends. Values over 1 make units prefer to run past their pursuer to escape.
+const FLEE_FACTOR_NUM: i32 = -12;
+const FLEE_FACTOR_DEN: i32 = 10;
+
+const NEIGHBOURS: [Point<i16>; 4] = [
+    Point{x: 0, y: -1},
+    Point{x: 0, y: 1},
+    Point{x: -1, y: 0},
+    Point{x: 1, y: 0},
+];
+
+// A map of how far every tile is from the nearest goal. Units can get to the
+// closest goal by always stepping to the neighbouring tile with the lowest
+// value, so one map can be shared by any number of units.
+#[derive(Debug)]
+pub struct DijkstraMap {
+    width: u8,
+    height: u8,
+    values: Vec<i32>,
+}
+
+impl DijkstraMap {
+    pub fn new<F>(width: u8, height: u8, goals: &[Point<i16>], passable: F) -> DijkstraMap
+        where F: Fn(Point<i16>) -> bool
+    {
+        let seeds: Vec<_> = goals.iter().map(|&g| (g, 0)).collect();
+        DijkstraMap::from_seeds(width, height, &seeds, passable)
+    }
+
+    // Builds a map where each seed tile starts with the given value, rather
+    // than all goals being equal.
+    fn from_seeds<F>(width: u8, height: u8, seeds: &[(Point<i16>, i32)], passable: F) -> DijkstraMap
+        where F: Fn(Point<i16>) -> bool
+    {
+        let mut map = DijkstraMap {
+            width: width,
+            height: height,
+            values: vec![i32::MAX; width as usize * height as usize],
+        };
+
+        let mut open = BinaryHeap::new();
+        for &(pos, value) in seeds.iter() {
+            if let Some(idx) = map.get_index(pos) {
+                if value < map.values[idx] {
+                    map.values[idx] = value;
+                    open.push(Reverse((value, pos.x, pos.y)));
+                }
+            }
+        }
+
+        while let Some(Reverse((value, x, y))) = open.pop() {
+            let pos = Point{x: x, y: y};
+            // A shorter route to this tile has already been handled.
+            if map.get_value(pos) != Some(value) {
+                continue;
+            }
+
+            for &dir in NEIGHBOURS.iter() {
+                let next = pos + dir;
+                let next_value = value + STEP_COST;
+
+                match map.get_index(next) {
+                    Some(idx) if next_value < map.values[idx] && passable(next) => {
+                        map.values[idx] = next_value;
+                        open.push(Reverse((next_value, next.x, next.y)));
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        map
+    }
+
+    // Creates a map for moving away from the goals of this one. Simply
+    // following this map uphill would lead units into dead ends, so instead
+    // the values are inverted and the map is rescanned.
+    pub fn inverted<F>(&self, passable: F) -> DijkstraMap
+        where F: Fn(Point<i16>) -> bool
+    {
+        let mut seeds = vec![];
+        for y in 0..self.height as i16 {
+            for x in 0..self.width as i16 {
+                let pos = Point{x: x, y: y};
+                if let Some(value) = self.get_value(pos) {
+                    seeds.push((pos, value * FLEE_FACTOR_NUM / FLEE_FACTOR_DEN));
+                }
+            }
+        }
+
+        DijkstraMap::from_seeds(self.width, self.height, &seeds, passable)
+    }
+
+    fn get_index(&self, Point{x, y}: Point<i16>) -> Option<usize> {
+        if x >= 0 && x < self.width as i16 && y >= 0 && y < self.height as i16 {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    // Returns None if the tile can't reach any goal.
+    fn get_value(&self, pos: Point<i16>) -> Option<i32> {
+        self.get_index(pos)
+            .map(|idx| self.values[idx])
+            .and_then(|v| if v == i32::MAX { None } else { Some(v) })
+    }
+
+    pub fn can_reach_goal(&self, pos: Point<i16>) -> bool {
+        self.get_value(pos).is_some()
+    }
+
+    // Finds the neighbouring tile that gets closest to a goal. Returns None if
+    // already at a goal, or every better tile is blocked.
+    pub fn get_downhill_step<F>(&self, pos: Point<i16>, can_enter: F) -> Option<Point<i16>>
+        where F: Fn(Point<i16>) -> bool
+    {
+        let mut best = self.get_value(pos)?;
+        let mut best_step = None;
+
+        for &dir in NEIGHBOURS.iter() {
+            let next = pos + dir;
+            match self.get_value(next) {
+                Some(value) if value < best && can_enter(next) => {
+                    best = value;
+                    best_step = Some(next);
+                },
+                _ => {}
+            }
+        }
+
+        best_step
+    }
+}
@@ -0,0 +1,57 @@
+use serde_yaml;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+const ERR_FACTION_LOAD: &str = "Unable to load faction data.";
+const ERR_UNKNOWN_FACTION: &str = "Faction is hostile to an unknown faction.";
+
+// The player's own faction. Anything in it fights alongside the player.
+pub const PLAYER_FACTION: &str = "player";
+
+#[derive(Debug, Deserialize)]
+struct FactionRaw {
+    name: String,
+    #[serde(default)]
+    hostile_to: Vec<String>,
+}
+
+pub struct Factions {
+    // The factions each faction fights with.
+    enemies: HashMap<String, Vec<String>>,
+}
+
+impl Factions {
+    pub fn get_enemies(&self, faction: &str) -> Option<&Vec<String>> {
+        self.enemies.get(faction)
+    }
+}
+
+// Hostility only has to be given one way in the data file, as it's always
+// mutual.
+pub fn load_factions() -> Factions {
+    let path = Path::new("data").join("factions.yaml");
+    let data_file = File::open(&path).expect(ERR_FACTION_LOAD);
+    let raw_factions: Vec<FactionRaw> = serde_yaml::from_reader(&data_file).expect(ERR_FACTION_LOAD);
+
+    let mut enemies: HashMap<String, Vec<String>> = raw_factions.iter()
+        .map(|f| (f.name.clone(), vec![]))
+        .collect();
+
+    for raw_faction in raw_factions.iter() {
+        for other in raw_faction.hostile_to.iter() {
+            enemies.get_mut(other).expect(ERR_UNKNOWN_FACTION).push(raw_faction.name.clone());
+            enemies.get_mut(&raw_faction.name).expect(ERR_UNKNOWN_FACTION).push(other.clone());
+        }
+    }
+
+    for list in enemies.values_mut() {
+        list.sort();
+        list.dedup();
+    }
+
+    Factions {
+        enemies: enemies,
+    }
+}
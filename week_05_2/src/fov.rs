@@ -0,0 +1,268 @@
+use point::Point;
+
+// Shadowcast is used unless the game is started with --tcod-fov.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FovAlgorithm {
+    Tcod,
+    Shadowcast,
+}
+
+// This is an implementation of symmetric shadowcasting, as described here:
+// https://www.albertford.com/shadowcasting/
+// The area around the origin is split into four quadrants, each of which is
+// scanned row by row, moving away from the origin. Walls narrow the range of
+// slopes that the next row needs to scan.
+//
+// Slopes are kept as fractions so that rounding doesn't cause any asymmetry.
+pub fn compute_fov<F>(origin: Point<i16>, radius: u8, blocks_sight: F) -> Vec<Point<i16>>
+    where F: Fn(Point<i16>) -> bool
+{
+    let mut visible = vec![origin];
+
+    for &cardinal in [Cardinal::North, Cardinal::East, Cardinal::South, Cardinal::West].iter() {
+        let quadrant = Quadrant { cardinal: cardinal, origin: origin };
+        scan_quadrant(&quadrant, radius as i16, &blocks_sight, &mut visible);
+    }
+
+    // The quadrants overlap along the diagonals, so remove the duplicates.
+    visible.sort_by_key(|p| (p.y, p.x));
+    visible.dedup();
+
+    visible
+}
+
+fn scan_quadrant<F>(quadrant: &Quadrant, radius: i16, blocks_sight: &F, visible: &mut Vec<Point<i16>>)
+    where F: Fn(Point<i16>) -> bool
+{
+    let sqr_radius = radius as i32 * radius as i32;
+    let mut rows = vec![Row {
+        depth: 1,
+        start_slope: Slope { num: -1, den: 1 },
+        end_slope: Slope { num: 1, den: 1 },
+    }];
+
+    while let Some(mut row) = rows.pop() {
+        if row.depth > radius {
+            continue;
+        }
+
+        let mut prev_was_wall = None;
+
+        for col in row.min_col()..row.max_col()+1 {
+            let pos = quadrant.transform(row.depth, col);
+            let is_wall = blocks_sight(pos);
+
+            let sqr_dist = row.depth as i32 * row.depth as i32 + col as i32 * col as i32;
+            if (is_wall || row.is_symmetric(col)) && sqr_dist <= sqr_radius {
+                visible.push(pos);
+            }
+
+            match (prev_was_wall, is_wall) {
+                (Some(true), false) => row.start_slope = Slope::from_tile(row.depth, col),
+                (Some(false), true) => {
+                    let mut next = row.next();
+                    next.end_slope = Slope::from_tile(row.depth, col);
+                    rows.push(next);
+                },
+                _ => {}
+            }
+
+            prev_was_wall = Some(is_wall);
+        }
+
+        if prev_was_wall == Some(false) {
+            rows.push(row.next());
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Cardinal {
+    North,
+    East,
+    South,
+    West,
+}
+
+struct Quadrant {
+    cardinal: Cardinal,
+    origin: Point<i16>,
+}
+
+impl Quadrant {
+    // Converts a row and column relative to the quadrant into a map position.
+    fn transform(&self, row: i16, col: i16) -> Point<i16> {
+        let Point{x, y} = self.origin;
+
+        match self.cardinal {
+            Cardinal::North => Point::new(x + col, y - row),
+            Cardinal::South => Point::new(x + col, y + row),
+            Cardinal::East  => Point::new(x + row, y + col),
+            Cardinal::West  => Point::new(x - row, y + col),
+        }
+    }
+}
+
+// A slope of num/den. The denominator is always positive.
+#[derive(Debug, Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    // The slope to the left edge of a tile, as seen from the origin.
+    fn from_tile(depth: i16, col: i16) -> Slope {
+        Slope {
+            num: 2 * col as i32 - 1,
+            den: 2 * depth as i32,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Row {
+    depth: i16,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    // floor(depth * start_slope + 0.5)
+    fn min_col(&self) -> i16 {
+        let Slope{num, den} = self.start_slope;
+        div_floor(2 * self.depth as i32 * num + den, 2 * den) as i16
+    }
+
+    // ceil(depth * end_slope - 0.5)
+    fn max_col(&self) -> i16 {
+        let Slope{num, den} = self.end_slope;
+        -div_floor(-(2 * self.depth as i32 * num - den), 2 * den) as i16
+    }
+
+    fn is_symmetric(&self, col: i16) -> bool {
+        let col = col as i32;
+        let depth = self.depth as i32;
+
+        col * self.start_slope.den >= depth * self.start_slope.num
+            && col * self.end_slope.den <= depth * self.end_slope.num
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start_slope: self.start_slope,
+            end_slope: self.end_slope,
+        }
+    }
+}
+
+fn div_floor(a: i32, b: i32) -> i32 {
+    let d = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) {
+        d - 1
+    } else {
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a blocks_sight function from rows of text, where '#' is a wall.
+    // Anything off the grid is treated as wall.
+    fn grid(rows: &'static [&'static str]) -> impl Fn(Point<i16>) -> bool {
+        move |Point{x, y}| {
+            if x < 0 || y < 0 {
+                return true;
+            }
+            rows.get(y as usize)
+                .and_then(|r| r.as_bytes().get(x as usize))
+                .is_none_or(|&c| c == b'#')
+        }
+    }
+
+    const ROOM: &[&str] = &[
+        "###########",
+        "#.........#",
+        "#..#......#",
+        "#.....#...#",
+        "#.........#",
+        "#...#...#.#",
+        "#.........#",
+        "###########",
+    ];
+
+    #[test]
+    fn fov_is_symmetric() {
+        let blocks_sight = grid(ROOM);
+        let floor: Vec<_> = (0..8).flat_map(|y| (0..11).map(move |x| Point{x: x, y: y}))
+            .filter(|&p| !blocks_sight(p))
+            .collect();
+
+        for &a in floor.iter() {
+            let seen_from_a = compute_fov(a, 20, &blocks_sight);
+
+            for &b in floor.iter() {
+                let seen_from_b = compute_fov(b, 20, &blocks_sight);
+                assert_eq!(seen_from_a.contains(&b), seen_from_b.contains(&a), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn walls_around_an_open_room_are_visible() {
+        const OPEN: &[&str] = &[
+            "#######",
+            "#.....#",
+            "#.....#",
+            "#.....#",
+            "#######",
+        ];
+        let visible = compute_fov(Point{x: 3, y: 2}, 10, grid(OPEN));
+
+        for y in 0..5 {
+            for x in 0..7 {
+                assert!(visible.contains(&Point{x: x, y: y}), "({}, {}) should be visible", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn pillars_cast_shadows() {
+        const PILLAR: &[&str] = &[
+            "...........",
+            "...........",
+            "...........",
+            "...#.......",
+            "...........",
+            "...........",
+            "...........",
+        ];
+        let origin = Point{x: 1, y: 3};
+        let visible = compute_fov(origin, 10, grid(PILLAR));
+
+        // The pillar itself is seen, but not what's straight behind it.
+        assert!(visible.contains(&Point{x: 3, y: 3}));
+        assert!(!visible.contains(&Point{x: 4, y: 3}));
+        assert!(!visible.contains(&Point{x: 8, y: 3}));
+
+        // The shadow doesn't spread out to the sides.
+        assert!(visible.contains(&Point{x: 8, y: 1}));
+        assert!(visible.contains(&Point{x: 8, y: 5}));
+    }
+
+    #[test]
+    fn nothing_beyond_the_radius_is_visible() {
+        const OPEN: &[&str] = &[
+            "...........",
+            "...........",
+            "...........",
+        ];
+        let visible = compute_fov(Point{x: 0, y: 1}, 3, grid(OPEN));
+
+        assert!(visible.contains(&Point{x: 3, y: 1}));
+        assert!(!visible.contains(&Point{x: 4, y: 1}));
+    }
+}
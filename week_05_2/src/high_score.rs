@@ -0,0 +1,82 @@
+use serde_yaml;
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+const HIGH_SCORE_FILE: &str = "high_scores.yaml";
+// Only this many of the best runs are kept.
+const MAX_HIGH_SCORES: usize = 20;
+
+// Each level reached is worth more than the last, so going deeper always
+// beats farming kills on a shallow level.
+const DEPTH_POINTS: u32 = 50;
+const KILL_POINTS: u32 = 10;
+const VICTORY_POINTS: u32 = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u32,
+    pub depth: u8,
+    // None if the player won.
+    pub killer: Option<String>,
+    pub turns: u32,
+    pub seed: u32,
+}
+
+pub fn calculate_score(depth: u8, kills: u32, victory: bool) -> u32 {
+    let depth = depth as u32;
+    let bonus = if victory { VICTORY_POINTS } else { 0 };
+    depth * depth * DEPTH_POINTS + kills * KILL_POINTS + bonus
+}
+
+// A missing file just means there are no scores yet. Anything else is an
+// error, so that a damaged table doesn't get replaced.
+pub fn load_high_scores() -> io::Result<Vec<HighScore>> {
+    let file = match File::open(Path::new(HIGH_SCORE_FILE)) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    serde_yaml::from_reader(&file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Adds the run to the table, returning its rank starting from 1, or None if
+// it wasn't good enough to be kept.
+pub fn add_high_score(entry: HighScore) -> io::Result<Option<usize>> {
+    let mut scores = load_high_scores()?;
+
+    // Runs with equal scores stay in the order they were played.
+    let rank = scores.iter().position(|s| s.score < entry.score).unwrap_or(scores.len());
+    scores.insert(rank, entry);
+    scores.truncate(MAX_HIGH_SCORES);
+
+    let file = File::create(Path::new(HIGH_SCORE_FILE))?;
+    serde_yaml::to_writer(file, &scores).map_err(io::Error::other)?;
+
+    Ok(if rank < MAX_HIGH_SCORES { Some(rank + 1) } else { None })
+}
+
+// One line per score, with a header, for the high score screen.
+pub fn get_table_lines(scores: &[HighScore]) -> Vec<String> {
+    if scores.is_empty() {
+        return vec!["No runs have been recorded yet.".into()];
+    }
+
+    let mut lines = vec![
+        format!("{:>3}  {:<16} {:>6} {:>5} {:>6} {:>10}  {}", "#", "Name", "Score", "Depth", "Turns", "Seed", "Fate"),
+        String::new(),
+    ];
+
+    for (i, s) in scores.iter().enumerate() {
+        let fate = match s.killer {
+            Some(ref killer) => format!("killed by {}", killer),
+            None => "victorious".into(),
+        };
+        lines.push(format!("{:>3}  {:<16} {:>6} {:>5} {:>6} {:>10}  {}", i + 1, s.name, s.score, s.depth, s.turns, s.seed, fate));
+    }
+
+    lines
+}
@@ -1,25 +1,56 @@
+use tcod;
 use tcod::colors::{Color};
 
 use traits::{Renderable, Position};
 use point::Point;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Gives every item its own id, so the player can tell when a new one comes
+// into view.
+static NEXT_ITEM_ID: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug)]
 pub struct Item {
+    id: usize,
     name: String,
     glyph: char,
     color: Color,
     position: Point<i16>,
+    // Corpses are left behind by every kill, so they're not worth stopping for.
+    is_corpse: bool,
 }
 
 impl Item {
     pub fn new(name: &str, glyph: char, color: Color, pos: Point<i16>) -> Item {
         Item {
+            id: NEXT_ITEM_ID.fetch_add(1, Ordering::Relaxed),
             name: name.into(),
             glyph: glyph,
             color: color,
             position: pos,
+            is_corpse: false,
+        }
+    }
+
+    pub fn new_corpse(unit_name: &str, glyph: char, pos: Point<i16>) -> Item {
+        Item {
+            is_corpse: true,
+            ..Item::new(&format!("{} corpse", unit_name), glyph, tcod::colors::DARK_RED, pos)
         }
     }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_corpse(&self) -> bool {
+        self.is_corpse
+    }
 }
 
 impl Position for Item {
@@ -0,0 +1,32 @@
+use tcod::colors::{Color};
+
+use point::Point;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightSource {
+    pub position: Point<i16>,
+    pub color: Color,
+    pub radius: u8,
+}
+
+impl LightSource {
+    pub fn new(pos: Point<i16>, color: Color, radius: u8) -> LightSource {
+        LightSource {
+            position: pos,
+            color: color,
+            radius: radius,
+        }
+    }
+
+    // Light fades linearly with distance, reaching nothing just past the radius.
+    pub fn get_color_at(&self, pos: Point<i16>) -> Color {
+        let dist = (pos - self.position).radius() as f32;
+        let falloff = 1.0 - dist / (self.radius as f32 + 1.0);
+
+        if falloff > 0.0 {
+            self.color * falloff
+        } else {
+            Color::new(0, 0, 0)
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#![cfg_attr(feature = "bench", feature(test))]
+
 extern crate num;
 extern crate rand;
 
@@ -8,10 +10,13 @@ extern crate serde_yaml;
 extern crate textwrap;
 
 extern crate tcod;
+
+#[cfg(all(test, feature = "bench"))]
+extern crate test;
 use tcod::RootConsole;
 use tcod::{Console};
-use tcod::console::{FontLayout, FontType, Offscreen};
-use tcod::input::{Key, KeyCode};
+use tcod::console::{FontLayout, FontType, Offscreen, BackgroundFlag};
+use tcod::input::{Key, KeyCode, Event, Mouse};
 
 mod traits;
 use traits::{Renderable, Movable, Position};
@@ -23,10 +28,29 @@ mod rectangle;
 mod item;
 mod units;
 mod unit_type;
+mod faction;
 mod map;
+mod pathfinding;
+mod dijkstra;
+mod noise;
+mod spawn_table;
+mod ai;
+mod ability;
+mod fov;
+mod light;
+mod auto_move;
+use auto_move::{AutoMove, Destination};
+use ui::MessageCategory;
 mod ui;
+mod theme;
+mod morgue;
+mod high_score;
+mod character_sheet;
+mod minimap;
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::thread;
+use std::time::Duration;
 
 const SCREEN_WIDTH: u8 = 80;
 const SCREEN_HEIGHT: u8 = 50;
@@ -35,15 +59,156 @@ const PANEL_Y: u8 = SCREEN_HEIGHT - PANEL_HEIGHT;
 
 const FOV_RADIUS: u8 = 10;
 
+// The input is polled rather than waited on, so that the mouse can be
+// tracked. The screen is only redrawn after something happens, at most this
+// many times a second, and while nothing does the input is checked this often.
+const LIMIT_FPS: i32 = 30;
+
+const TORCH_RADIUS: u8 = 8;
+const TORCH_COLOR: tcod::Color = tcod::Color{r: 255, g: 210, b: 150};
+
+// Running the game with this prints the monster spawn chances at each depth
+// instead.
+const SPAWN_REPORT_ARG: &str = "--spawn-report";
+// Uses the colour-blind friendly theme for messages.
+const COLOUR_BLIND_ARG: &str = "--colour-blind";
+// Uses libtcod's FOV instead of the native shadowcasting, for comparison.
+const TCOD_FOV_ARG: &str = "--tcod-fov";
+// Followed by the name to put on the high score table.
+const NAME_ARG: &str = "--name";
+const DEFAULT_NAME: &str = "Stranger";
+// Followed by a number, which every new run uses to build the dungeon.
+const SEED_ARG: &str = "--seed";
+const ERR_SEED: &str = "The seed must be a whole number.";
+
+// The companion the player starts with.
+const PET_NAME: &str = "Dog";
+const ERR_PET: &str = "Unable to find the pet's unit type.";
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 enum GameState {
     Playing,
     Dead,
+    Victory,
     NewMap,
+    // The title screen.
     Menu,
+    HighScores,
+    Character,
+    Paused,
+    // The previous menu is stored, so that it can be returned to.
+    Options(MenuState),
+    // Stores the item that would leave the run, so that it can be carried out
+    // once confirmed.
+    ConfirmAbandon(MenuItem),
+    Targeting,
+    // Looking through the message history.
+    History,
     Exit,
 }
 
+// The menus that can open the options.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum MenuState {
+    Menu,
+    Paused,
+}
+
+impl MenuState {
+    fn to_game_state(self) -> GameState {
+        match self {
+            MenuState::Menu => GameState::Menu,
+            MenuState::Paused => GameState::Paused,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum MenuItem {
+    NewGame,
+    HighScores,
+    Options,
+    Quit,
+    Resume,
+    // Back to a run that was left for the main menu.
+    ReturnToRun,
+    MainMenu,
+    Abandon,
+    Theme,
+    Back,
+    Yes,
+    No,
+}
+
+impl MenuItem {
+    fn get_label(self, theme_name: &str) -> String {
+        match self {
+            MenuItem::NewGame => "New Game".into(),
+            MenuItem::HighScores => "High Scores".into(),
+            MenuItem::Options => "Options".into(),
+            MenuItem::Quit => "Quit".into(),
+            MenuItem::Resume => "Resume".into(),
+            MenuItem::ReturnToRun => "Return to Run".into(),
+            MenuItem::MainMenu => "Main Menu".into(),
+            MenuItem::Abandon => "Abandon Run".into(),
+            MenuItem::Theme => {
+                let name = if theme_name == theme::COLOUR_BLIND_THEME { "Colour Blind" } else { "Default" };
+                format!("Message Colours: {}", name)
+            },
+            MenuItem::Back => "Back".into(),
+            MenuItem::Yes => "Yes".into(),
+            MenuItem::No => "No".into(),
+        }
+    }
+}
+
+// Returns None if the game state isn't a menu.
+fn get_menu(game_state: GameState, run_in_progress: bool) -> Option<(&'static str, Vec<MenuItem>)> {
+    match game_state {
+        GameState::Menu => {
+            let mut items = vec![MenuItem::NewGame];
+            // Runs are only kept in memory, so this goes back to one that was
+            // left for the main menu. Saving to disk isn't supported, so
+            // quitting or starting a new game loses it.
+            if run_in_progress {
+                items.push(MenuItem::ReturnToRun);
+            }
+            items.extend(&[MenuItem::HighScores, MenuItem::Options, MenuItem::Quit]);
+            Some(("Tombs of the Ancient Kings", items))
+        },
+        GameState::Paused => Some(("Paused", vec![MenuItem::Resume, MenuItem::Options, MenuItem::MainMenu, MenuItem::Abandon])),
+        GameState::Options(_) => Some(("Options", vec![MenuItem::Theme, MenuItem::Back])),
+        // No comes first, so that it's hard to abandon a run by accident.
+        GameState::ConfirmAbandon(_) => Some(("Abandon this run?", vec![MenuItem::No, MenuItem::Yes])),
+        _ => None,
+    }
+}
+
+// Moves the selection, returning the chosen item once one is picked. Escape
+// picks the item that backs out of the menu, if there is one.
+fn handle_menu_input(root: &mut RootConsole, game_state: GameState, items: &[MenuItem], selection: &mut usize) -> Option<MenuItem> {
+    let key = root.wait_for_keypress(true);
+
+    match key_type(&key) {
+        KeyType::Movement(Direction::Up) => {
+            *selection = (*selection + items.len() - 1) % items.len();
+            None
+        },
+        KeyType::Movement(Direction::Down) => {
+            *selection = (*selection + 1) % items.len();
+            None
+        },
+        KeyType::Confirm => items.get(*selection).cloned(),
+        KeyType::Exit => match game_state {
+            GameState::Paused => Some(MenuItem::Resume),
+            GameState::Options(_) => Some(MenuItem::Back),
+            GameState::ConfirmAbandon(_) => Some(MenuItem::No),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 enum PlayerAction {
     Moved,
@@ -74,8 +239,20 @@ impl Direction {
 #[derive(Debug)]
 enum KeyType {
     Movement(Direction),
+    Explore,
+    Travel,
+    Stairs,
+    Sneak,
+    Follow,
+    Stay,
+    History,
+    Character,
+    Minimap,
+    PageUp,
+    PageDown,
+    Tab,
+    Confirm,
     Exit,
-    NewGame,
     Other,
 }
 
@@ -86,17 +263,29 @@ fn key_type(key: &Key) -> KeyType {
         KeyCode::Up     =>  KeyType::Movement(Direction::Up),
         KeyCode::Down   =>  KeyType::Movement(Direction::Down),
         KeyCode::Escape =>  KeyType::Exit,
-        KeyCode::F1     =>  KeyType::NewGame,
+        KeyCode::Enter  =>  KeyType::Confirm,
+        KeyCode::PageUp =>  KeyType::PageUp,
+        KeyCode::PageDown => KeyType::PageDown,
+        KeyCode::Tab    =>  KeyType::Tab,
+        KeyCode::Char   =>  match key.printable {
+            'x' => KeyType::Explore,
+            't' => KeyType::Travel,
+            '>' => KeyType::Stairs,
+            's' => KeyType::Sneak,
+            'f' => KeyType::Follow,
+            'w' => KeyType::Stay,
+            'm' => KeyType::History,
+            'c' => KeyType::Character,
+            'o' => KeyType::Minimap,
+            _ => KeyType::Other,
+        },
         _ => KeyType::Other,
     }
 }
 
-fn render_all<'a>(root: &mut RootConsole, buffer_console: &mut Offscreen, ui: &mut ui::UI, map: &map::Map, npcs: &VecDeque<units::Unit<'a>>, player: &units::Unit) {
-    buffer_console.clear();
-    root.clear();
-
+fn get_view_port(map: &map::Map, player: &units::Unit) -> rectangle::Rectangle {
     // With the scrolling map, we need to try to centre the player on the screen
-    // without going past the bounds of the buffer.
+    // without going past the bounds of the map.
     let (map_width, map_height) = map.get_map_size();
     let draw_left = player.get_x() - SCREEN_WIDTH as i16 / 2;
     let draw_top = player.get_y() - SCREEN_HEIGHT as i16 / 2;
@@ -104,44 +293,270 @@ fn render_all<'a>(root: &mut RootConsole, buffer_console: &mut Offscreen, ui: &m
     let mut view_port = rectangle::Rectangle::new(Point{x: draw_left, y: draw_top}, (SCREEN_WIDTH, SCREEN_HEIGHT - PANEL_HEIGHT));
     view_port.clamp_to((0,0), (map_width as i16, map_height as i16));
 
-    map.render_map(buffer_console);
+    view_port
+}
+
+// Converts a cell of the root console into a position on the map, if the
+// cell is part of the map view and not the UI panel or the minimap.
+fn screen_to_map(map: &map::Map, player: &units::Unit, cell: Point<i16>, show_minimap: bool) -> Option<Point<i16>> {
+    if cell.x < 0 || cell.x >= SCREEN_WIDTH as i16 || cell.y < 0 || cell.y >= (SCREEN_HEIGHT - PANEL_HEIGHT) as i16 {
+        return None;
+    }
+
+    // The minimap is drawn on the same console as the map view.
+    if show_minimap && minimap::get_minimap_rect(map, SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32).contains_point(cell) {
+        return None;
+    }
+
+    // The same offset that render_all uses for the blit.
+    let pos = cell + get_view_port(map, player).top_left;
+    if map.point_in_map(pos) { Some(pos) } else { None }
+}
+
+// Names everything the player can see on the tile under the given cell.
+fn describe_tile<'a>(map: &map::Map, npcs: &VecDeque<units::Unit<'a>>, player: &units::Unit, cell: Point<i16>, show_minimap: bool) -> Option<String> {
+    let pos = screen_to_map(map, player, cell, show_minimap)?;
+    let player_pos = player.get_position();
+    if !map.point_in_fov(pos) {
+        return None;
+    }
+
+    let mut names: Vec<&str> = vec![];
+    if pos == player_pos && player.get_hp() > 0 {
+        names.push("You");
+    }
+    if map.can_see_unit(pos, player_pos) {
+        names.extend(npcs.iter().filter(|n| n.get_position() == pos && n.get_hp() > 0).map(|n| n.get_name()));
+    }
+    names.extend(map.get_item_names_at(pos));
+
+    if names.is_empty() { None } else { Some(names.join(", ")) }
+}
+
+// Everything the player's input might need to look at or change.
+struct InputContext<'a: 'b, 'b> {
+    map: &'b mut map::Map,
+    ui: &'b mut ui::UI,
+    npcs: &'b mut VecDeque<units::Unit<'a>>,
+    player: &'b mut units::Unit<'a>,
+    auto_move: &'b mut Option<AutoMove>,
+    // Where the travel cursor is while picking a destination.
+    cursor: &'b mut Point<i16>,
+    sneaking: &'b mut bool,
+    show_minimap: &'b mut bool,
+    stats: &'b mut morgue::RunStats,
+    // The cell the mouse was last over, if any.
+    mouse_cell: &'b mut Option<Point<i16>>,
+}
+
+// Returns false if the mouse only moved within the same cell, as then there's
+// nothing new to show.
+fn handle_mouse<'a, 'b>(mouse: Mouse, cur_game_state: GameState, ctx: &mut InputContext<'a, 'b>) -> bool {
+    let cell = Point{x: mouse.cx as i16, y: mouse.cy as i16};
+    let moved = *ctx.mouse_cell != Some(cell);
+    *ctx.mouse_cell = Some(cell);
+
+    if !mouse.lbutton_pressed || cur_game_state != GameState::Playing {
+        return moved;
+    }
+
+    // Clicking travels the same way as picking a spot with the travel key.
+    match screen_to_map(ctx.map, ctx.player, cell, *ctx.show_minimap) {
+        Some(pos) if ctx.map.is_explored(pos) => *ctx.auto_move = Some(AutoMove::new(Destination::Point(pos), ctx.map, ctx.npcs, ctx.player)),
+        Some(_) => ctx.ui.add_message("You don't know what's there.", MessageCategory::System),
+        None => {},
+    }
+    true
+}
+
+fn update_lighting<'a>(map: &mut map::Map, npcs: &VecDeque<units::Unit<'a>>, player: &units::Unit) {
+    let player_pos = player.get_position();
+    let mut lights = vec![];
+
+    if player.get_hp() > 0 {
+        lights.push(light::LightSource::new(player_pos, TORCH_COLOR, TORCH_RADIUS));
+    }
+
+    // Lights too far away to reach anything the player can see are skipped.
+    let npc_lights = npcs.iter()
+        .filter_map(|n| n.get_light_source())
+        .filter(|l| (l.position - player_pos).radius() <= (FOV_RADIUS + l.radius) as f64);
+    lights.extend(npc_lights);
+
+    map.update_lighting(&lights);
+}
+
+// What gets drawn over the map, on top of the units.
+#[derive(Debug, Copy, Clone)]
+struct Overlay {
+    // The tile picked while targeting.
+    cursor: Option<Point<i16>>,
+    show_minimap: bool,
+}
+
+fn render_all<'a>(root: &mut RootConsole, buffer_console: &mut Offscreen, ui: &mut ui::UI, map: &map::Map, npcs: &VecDeque<units::Unit<'a>>, player: &units::Unit, overlay: Overlay) {
+    buffer_console.clear();
+    root.clear();
+
+    // The buffer is only the size of the view port, so everything is drawn
+    // relative to its top-left corner.
+    let view_port = get_view_port(map, player);
+    let offset = view_port.top_left;
+
+    map.render_map(buffer_console, &view_port);
     
     for unit in npcs.iter() {
-        if map.point_in_fov(unit.get_position()) {
-            unit.render(buffer_console);
+        let pos = unit.get_position();
+        if view_port.contains_point(pos) && map.can_see_unit(pos, player.get_position()) {
+            unit.render(buffer_console, offset);
         }
     }
 
     // If the player has died, a corpse will have been created.
     if player.get_hp() > 0 {
-        player.render(buffer_console);
+        player.render(buffer_console, offset);
+    }
+
+    if let Some(cursor) = overlay.cursor {
+        let Point{x, y} = cursor - offset;
+        buffer_console.set_char_background(x as i32, y as i32, tcod::colors::LIGHT_GREY, BackgroundFlag::Set);
     }
 
-    tcod::console::blit(buffer_console, (view_port.top_left.x as i32, view_port.top_left.y as i32), (SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32), root, (0,0), 1.0, 1.0);
+    if overlay.show_minimap {
+        minimap::render_minimap(buffer_console, map, npcs, player);
+    }
+
+    tcod::console::blit(buffer_console, (0, 0), (SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32), root, (0,0), 1.0, 1.0);
 
     ui.render(root);
 
     root.flush();
 }
 
-fn handle_input<'a>(root: &mut RootConsole, cur_game_state: GameState, map: &map::Map, ui: &mut ui::UI, npcs: &mut VecDeque<units::Unit<'a>>, player: &mut units::Unit) -> (PlayerAction, GameState) {
-    let key = root.wait_for_keypress(true);
+// Puts allies next to the player, such as when they follow the player to a new level.
+fn place_allies<'a>(map: &mut map::Map, npcs: &mut VecDeque<units::Unit<'a>>, allies: Vec<units::Unit<'a>>, player_pos: Point<i16>) {
+    for mut ally in allies {
+        // Any that can't fit are left behind.
+        if let Some(pos) = map.get_free_neighbour(player_pos, player_pos) {
+            ally.move_to(pos);
+            map.add_occupant(&ally);
+            npcs.push_back(ally);
+        }
+    }
+}
+
+fn order_allies<'a>(ui: &mut ui::UI, npcs: &mut VecDeque<units::Unit<'a>>, state: units::AiState, message: &str) {
+    let mut any_allies = false;
+    for ally in npcs.iter_mut().filter(|n| n.is_ally() && n.get_hp() > 0) {
+        let new_state = match state {
+            units::AiState::Staying(_) => units::AiState::Staying(ally.get_position()),
+            _ => state,
+        };
+        ally.set_ai_state(new_state);
+        any_allies = true;
+    }
+
+    if any_allies {
+        ui.add_message(message, MessageCategory::System);
+    } else {
+        ui.add_message("You have no one to give orders to.", MessageCategory::System);
+    }
+}
+
+// Sets up a fresh run, returning the first level's map and NPCs, and the player.
+fn new_game<'a>(unit_types: &'a unit_type::UnitTypeLists, player_type: &'a unit_type::UnitType, seed: u32, fov_algorithm: fov::FovAlgorithm, spawned_uniques: &mut HashSet<String>) -> (map::Map, VecDeque<units::Unit<'a>>, units::Unit<'a>) {
+    spawned_uniques.clear();
+    let (mut map, mut npcs, start_coord) = map::Map::init(unit_types, 1, seed, spawned_uniques);
+
+    let player = units::Unit::new(start_coord, player_type);
+
+    let mut pet = units::Unit::new(start_coord, unit_types.get_type_by_name(PET_NAME).expect(ERR_PET));
+    pet.set_ai_state(units::AiState::Following);
+    place_allies(&mut map, &mut npcs, vec![pet], start_coord);
+
+    map.update_fov(player.get_position(), FOV_RADIUS, fov_algorithm);
+    map.update_player_maps(player.get_position());
+
+    (map, npcs, player)
+}
+
+fn new_ui(player: &units::Unit, theme_name: &str) -> ui::UI {
+    let max_hp = player.get_unit_type().get_max_hp() as i16;
+    let mut ui = ui::UI::new(Point{x: 0, y: PANEL_Y as i16}, SCREEN_WIDTH as i32, PANEL_HEIGHT as i32, max_hp, theme::load_theme(theme_name));
+    ui.add_message("Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.", MessageCategory::Flavour);
+    ui
+}
+
+fn kill_player(map: &mut map::Map, ui: &mut ui::UI, player: &units::Unit, stats: &mut morgue::RunStats, cause: &str) {
+    ui.add_message("You died!", MessageCategory::DamageTaken);
+    stats.set_cause_of_death(cause);
+    let corpse = item::Item::new_corpse(player.get_name(), player.get_glyph(), player.get_position());
+    map.place_item(corpse);
+}
+
+fn take_auto_step<'a>(map: &map::Map, ui: &mut ui::UI, npcs: &VecDeque<units::Unit<'a>>, player: &mut units::Unit, auto_move: &mut Option<AutoMove>) -> PlayerAction {
+    // Any key press stops the player.
+    if let Some((_, Event::Key(_))) = tcod::input::check_for_event(tcod::input::KEY_PRESS) {
+        *auto_move = None;
+        return PlayerAction::NoTurn;
+    }
+
+    match auto_move.as_mut().and_then(|a| a.next_step(map, ui, npcs, player)) {
+        Some(new_pos) => {
+            player.move_to(new_pos);
+            PlayerAction::Moved
+        },
+        None => {
+            *auto_move = None;
+            PlayerAction::NoTurn
+        }
+    }
+}
+
+// Returns None if nothing happened that needs the screen redrawing.
+fn handle_input<'a, 'b>(cur_game_state: GameState, ctx: &mut InputContext<'a, 'b>) -> Option<(PlayerAction, GameState)> {
+    let key = match tcod::input::check_for_event(tcod::input::KEY_PRESS | tcod::input::MOUSE) {
+        Some((_, Event::Key(key))) => key,
+        Some((_, Event::Mouse(mouse))) => {
+            if handle_mouse(mouse, cur_game_state, ctx) {
+                return Some((PlayerAction::NoTurn, cur_game_state));
+            }
+            return None;
+        },
+        None => return None,
+    };
 
     let mut player_action: PlayerAction = PlayerAction::NoTurn;
     let mut new_game_state: GameState = cur_game_state;
 
     match key_type(&key) {
         KeyType::Movement(dir) if cur_game_state == GameState::Playing => {
-            let pos = player.get_position();
+            let pos = ctx.player.get_position();
             let new_pos = pos + dir.to_rel_point();
 
-            player_action = match map.can_move_to(new_pos) {
+            player_action = match ctx.map.can_move_to(new_pos) {
                 map::CanMoveResponse::Open => {
-                    if let Some(enemy) = npcs.iter_mut().filter(|n| n.get_position() == new_pos).next() {
-                        player.attack(enemy, ui);
-                        PlayerAction::Turn
+                    if let Some(other) = ctx.npcs.iter_mut().filter(|n| n.get_position() == new_pos).next() {
+                        if other.is_ally() {
+                            ctx.ui.add_message(&format!("You swap places with the {}.", other.get_name()), MessageCategory::Flavour);
+                            other.walk_to(ctx.map, pos);
+                            ctx.player.move_to(new_pos);
+                            PlayerAction::Moved
+                        } else {
+                            ctx.player.attack(other, ctx.ui);
+                            if other.get_hp() == 0 {
+                                ctx.stats.add_kill(other.get_unit_type().get_name());
+
+                                // Tougher monsters are worth more experience.
+                                if ctx.player.gain_xp(other.get_unit_type().get_max_hp() as u32) > 0 {
+                                    ctx.ui.add_message(&format!("You feel stronger! Welcome to level {}.", ctx.player.get_level()), MessageCategory::System);
+                                }
+                            }
+                            ctx.map.make_noise(new_pos, noise::COMBAT_LOUDNESS);
+                            PlayerAction::Turn
+                        }
                     } else {
-                        player.move_to(new_pos);
+                        ctx.player.move_to(new_pos);
                         PlayerAction::Moved
                     }
                 },
@@ -150,22 +565,116 @@ fn handle_input<'a>(root: &mut RootConsole, cur_game_state: GameState, map: &map
                 } // Nothing to do.
             };
         },
+        KeyType::Movement(dir) if cur_game_state == GameState::Targeting => {
+            let new_cursor = *ctx.cursor + dir.to_rel_point();
+            if ctx.map.point_in_map(new_cursor) {
+                *ctx.cursor = new_cursor;
+            }
+        },
+        KeyType::Movement(Direction::Up) if cur_game_state == GameState::History => ctx.ui.scroll_history(1),
+        KeyType::Movement(Direction::Down) if cur_game_state == GameState::History => ctx.ui.scroll_history(-1),
         KeyType::Movement(_) if cur_game_state == GameState::Menu => {} // Will likely be used for menus
         KeyType::Movement(_) => {},
 
+        // The actual moving is done a step at a time in the main loop.
+        KeyType::Explore if cur_game_state == GameState::Playing => {
+            *ctx.auto_move = Some(AutoMove::new(Destination::Unexplored, ctx.map, ctx.npcs, ctx.player));
+        },
+        KeyType::Explore => {},
+
+        KeyType::Travel if cur_game_state == GameState::Playing => {
+            *ctx.cursor = ctx.player.get_position();
+            new_game_state = GameState::Targeting;
+        },
+        KeyType::Travel => {},
+
+        KeyType::Confirm if cur_game_state == GameState::Targeting => {
+            *ctx.auto_move = Some(AutoMove::new(Destination::Point(*ctx.cursor), ctx.map, ctx.npcs, ctx.player));
+            new_game_state = GameState::Playing;
+        },
+        KeyType::Confirm => {},
+
+        KeyType::Stairs if cur_game_state == GameState::Playing => {
+            if ctx.map.is_stairs(ctx.player.get_position()) {
+                new_game_state = GameState::NewMap;
+            } else if let Some(stairs) = ctx.map.get_known_stairs() {
+                *ctx.auto_move = Some(AutoMove::new(Destination::Point(stairs), ctx.map, ctx.npcs, ctx.player));
+            } else {
+                ctx.ui.add_message("You don't know where the stairs are.", MessageCategory::System);
+            }
+        },
+        KeyType::Stairs => {},
+
+        KeyType::Sneak if cur_game_state == GameState::Playing => {
+            *ctx.sneaking = !*ctx.sneaking;
+            if *ctx.sneaking {
+                ctx.ui.add_message("You start moving quietly.", MessageCategory::System);
+            } else {
+                ctx.ui.add_message("You stop ctx.sneaking.", MessageCategory::System);
+            }
+        },
+        KeyType::Sneak => {},
+
+        KeyType::Follow if cur_game_state == GameState::Playing => {
+            order_allies(ctx.ui, ctx.npcs, units::AiState::Following, "You call your allies to follow you.");
+        },
+        KeyType::Follow => {},
+
+        KeyType::Stay if cur_game_state == GameState::Playing => {
+            order_allies(ctx.ui, ctx.npcs, units::AiState::Staying(ctx.player.get_position()), "You tell your allies to stay.");
+        },
+        KeyType::Stay => {},
+
+        KeyType::History if cur_game_state == GameState::Playing => {
+            ctx.ui.reset_history_scroll();
+            new_game_state = GameState::History;
+        },
+        KeyType::History => {},
+
+        KeyType::Character if cur_game_state == GameState::Playing => {
+            new_game_state = GameState::Character;
+        },
+        KeyType::Character => {},
+
+        KeyType::Minimap if cur_game_state == GameState::Playing => *ctx.show_minimap = !*ctx.show_minimap,
+        KeyType::Minimap => {},
+
+        KeyType::PageUp if cur_game_state == GameState::History => ctx.ui.scroll_history_page(true),
+        KeyType::PageDown if cur_game_state == GameState::History => ctx.ui.scroll_history_page(false),
+        KeyType::PageUp | KeyType::PageDown => {},
+
+        KeyType::Tab if cur_game_state == GameState::History => ctx.ui.cycle_history_filter(),
+        KeyType::Tab => {},
+
+        KeyType::Exit if cur_game_state == GameState::Targeting || cur_game_state == GameState::History || cur_game_state == GameState::Character => {
+            new_game_state = GameState::Playing;
+        },
+        KeyType::Exit if cur_game_state == GameState::Playing => {
+            new_game_state = GameState::Paused;
+        },
+        KeyType::Exit if cur_game_state == GameState::Dead || cur_game_state == GameState::Victory => {
+            new_game_state = GameState::Menu;
+        },
         KeyType::Exit           => {
             new_game_state = GameState::Exit;
         },
-        KeyType::NewGame        => {
-            new_game_state = GameState::NewMap;
-        }
         KeyType::Other          => println!("{:?}", key),
     }
 
-    (player_action, new_game_state)
+    Some((player_action, new_game_state))
+}
+
+// The value following the given argument, if it was passed.
+fn get_arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|a| a != name).nth(1)
 }
 
 fn main() {
+    if std::env::args().any(|a| a == SPAWN_REPORT_ARG) {
+        spawn_table::print_spawn_report(&unit_type::load_unit_types());
+        return;
+    }
+
     let mut root = RootConsole::initializer()
                     .size(SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32)
                     .title("Roguelike Tutorial")
@@ -174,44 +683,260 @@ fn main() {
                     .font_type(FontType::Greyscale)
                     .init();
 
-    let mut buffer_console = Offscreen::new(map::MAP_MAX_WIDTH as i32, map::MAP_MAX_HEIGHT as i32);
-    root.set_default_foreground(tcod::colors::WHITE);
+    tcod::system::set_fps(LIMIT_FPS);
 
-    let unit_types = unit_type::load_unit_types();
-    let (mut map, mut npcs, start_coord) = map::Map::init(&unit_types);
+    let mut buffer_console = Offscreen::new(SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32);
+    root.set_default_foreground(tcod::colors::WHITE);
 
+    // The player's type needs to outlive the NPCs, as they share a lifetime.
     let player_type = unit_type::UnitType::new("Player", '@', tcod::colors::WHITE);
-    let mut player = units::Unit::new(start_coord, &player_type);
+    let unit_types = unit_type::load_unit_types();
 
-    let mut ui = ui::UI::new(Point{x: 0, y: PANEL_Y as i16}, SCREEN_WIDTH as i32, PANEL_HEIGHT as i32, player_type.get_max_hp() as i16);
+    let player_name = get_arg_value(NAME_ARG).unwrap_or_else(|| DEFAULT_NAME.into());
+    let fixed_seed = get_arg_value(SEED_ARG).map(|s| s.parse::<u32>().expect(ERR_SEED));
+    let fov_algorithm = if std::env::args().any(|a| a == TCOD_FOV_ARG) { fov::FovAlgorithm::Tcod } else { fov::FovAlgorithm::Shadowcast };
 
-    ui.add_message("Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.", tcod::colors::RED);
+    // Unique monsters are tracked for the whole game, so each only turns up once.
+    let mut spawned_uniques = HashSet::new();
+    let mut stats = morgue::RunStats::new(fixed_seed.unwrap_or_else(rand::random));
+    // A run is set up straight away, so that there's always something to
+    // draw behind the menus. It isn't shown until a new game is started.
+    let (mut map, mut npcs, mut player) = new_game(&unit_types, &player_type, stats.get_seed(), fov_algorithm, &mut spawned_uniques);
 
-    map.update_fov(player.get_position(), FOV_RADIUS);
+    let mut theme_name = if std::env::args().any(|a| a == COLOUR_BLIND_ARG) { theme::COLOUR_BLIND_THEME } else { theme::DEFAULT_THEME };
+    let mut ui = new_ui(&player, theme_name);
 
-    let mut game_state = GameState::Playing;
+    let mut game_state = GameState::Menu;
+    let mut run_in_progress = false;
+    let mut menu_selection = 0;
+    let mut auto_move = None;
+    let mut cursor = player.get_position();
+    let mut sneaking = false;
+    let mut show_minimap = false;
+    // The console cell under the mouse, once it's been moved.
+    let mut mouse_cell = None;
+    // Set once the run has ended and been recorded, saying where it was saved.
+    let mut run_notes: Option<Vec<String>> = None;
+    // Set while waiting for input, so the screen isn't worked out again
+    // when nothing has changed.
+    let mut idle = false;
 
     while !root.window_closed() {
-        ui.update_hp_val(player.get_hp() as i16);
+        if !idle {
+            ui.update_hp_val(player.get_hp() as i16);
+            update_lighting(&mut map, &npcs, &player);
+        }
 
-        render_all(&mut root, &mut buffer_console, &mut ui, &map, &npcs, &player);
+        // Finished runs are recorded once, as soon as they end.
+        if (game_state == GameState::Dead || game_state == GameState::Victory) && run_notes.is_none() {
+            let victory = game_state == GameState::Victory;
+            let mut notes = vec![];
 
-        let (player_action, new_game_state) = handle_input(&mut root, game_state, &map, &mut ui, &mut npcs, &mut player);
+            if !victory {
+                notes.push(match morgue::write_morgue(&stats, &player, &map, &npcs, &ui) {
+                    Ok(path) => format!("A morgue file was written to {}.", path.display()),
+                    Err(e) => format!("Unable to write the morgue file: {}", e),
+                });
+            }
+
+            let entry = high_score::HighScore {
+                name: player_name.clone(),
+                score: high_score::calculate_score(map.get_depth(), stats.get_kill_count(), victory),
+                depth: map.get_depth(),
+                killer: stats.get_cause_of_death().map(String::from),
+                turns: stats.get_turns(),
+                seed: stats.get_seed(),
+            };
+            notes.push(match high_score::add_high_score(entry) {
+                Ok(Some(rank)) => format!("Your run ranks #{} on the high score table.", rank),
+                Ok(None) => "Your run didn't make the high score table.".into(),
+                Err(e) => format!("Unable to save the high score: {}", e),
+            });
+
+            // There's no separate screen for winning, so the notes go in the log.
+            if victory {
+                for note in notes.iter() {
+                    ui.add_message(note, MessageCategory::System);
+                }
+            }
+            run_notes = Some(notes);
+        }
+
+        if game_state == GameState::HighScores {
+            let lines = match high_score::load_high_scores() {
+                Ok(scores) => high_score::get_table_lines(&scores),
+                Err(e) => vec![format!("Unable to load the high scores: {}", e)],
+            };
+            ui::render_text_screen(&mut root, "High Scores", &lines, "Esc: back");
+            root.flush();
+
+            match key_type(&root.wait_for_keypress(true)) {
+                KeyType::Exit | KeyType::Confirm => game_state = GameState::Menu,
+                _ => {},
+            }
+            continue;
+        }
+
+        if let Some((title, items)) = get_menu(game_state, run_in_progress) {
+            root.set_default_background(tcod::colors::BLACK);
+            root.clear();
+            // The game is still shown behind the pause menu.
+            if run_in_progress && game_state != GameState::Menu {
+                render_all(&mut root, &mut buffer_console, &mut ui, &map, &npcs, &player, Overlay{ cursor: None, show_minimap: show_minimap });
+            }
+
+            let labels: Vec<String> = items.iter().map(|i| i.get_label(theme_name)).collect();
+            ui::render_menu(&mut root, title, &labels, menu_selection);
+            root.flush();
+
+            let choice = handle_menu_input(&mut root, game_state, &items, &mut menu_selection);
+            if choice.is_some() {
+                menu_selection = 0;
+            }
+
+            // Anything that would lose the run in progress has to be
+            // confirmed first, and is carried out once it is.
+            let choice = match (choice, game_state) {
+                (Some(item @ MenuItem::NewGame), GameState::Menu) | (Some(item @ MenuItem::Quit), GameState::Menu) if run_in_progress => {
+                    game_state = GameState::ConfirmAbandon(item);
+                    None
+                },
+                (Some(MenuItem::Yes), GameState::ConfirmAbandon(item)) => {
+                    run_in_progress = false;
+                    // Abandoning from the pause menu goes back to the title screen.
+                    if item == MenuItem::Abandon { Some(MenuItem::MainMenu) } else { Some(item) }
+                },
+                _ => choice,
+            };
+
+            match choice {
+                Some(MenuItem::NewGame) => {
+                    stats = morgue::RunStats::new(fixed_seed.unwrap_or_else(rand::random));
+                    let (new_map, new_npcs, new_player) = new_game(&unit_types, &player_type, stats.get_seed(), fov_algorithm, &mut spawned_uniques);
+                    map = new_map;
+                    npcs = new_npcs;
+                    player = new_player;
+                    ui = new_ui(&player, theme_name);
+                    auto_move = None;
+                    sneaking = false;
+                    run_notes = None;
+                    run_in_progress = true;
+                    game_state = GameState::Playing;
+                },
+                Some(MenuItem::Resume) | Some(MenuItem::ReturnToRun) => game_state = GameState::Playing,
+                Some(MenuItem::HighScores) => game_state = GameState::HighScores,
+                Some(MenuItem::Options) => {
+                    let from = if game_state == GameState::Paused { MenuState::Paused } else { MenuState::Menu };
+                    game_state = GameState::Options(from);
+                },
+                Some(MenuItem::Quit) => break,
+                Some(MenuItem::MainMenu) => game_state = GameState::Menu,
+                Some(MenuItem::Abandon) => game_state = GameState::ConfirmAbandon(MenuItem::Abandon),
+                Some(MenuItem::Theme) => {
+                    theme_name = if theme_name == theme::COLOUR_BLIND_THEME { theme::DEFAULT_THEME } else { theme::COLOUR_BLIND_THEME };
+                    ui.set_theme(theme::load_theme(theme_name));
+                },
+                Some(MenuItem::Back) => {
+                    if let GameState::Options(from) = game_state {
+                        game_state = from.to_game_state();
+                    }
+                },
+                Some(MenuItem::No) => {
+                    if let GameState::ConfirmAbandon(item) = game_state {
+                        game_state = if item == MenuItem::Abandon { GameState::Paused } else { GameState::Menu };
+                    }
+                },
+                // Handled above, before the run is lost.
+                Some(MenuItem::Yes) | None => {},
+            }
+
+            continue;
+        }
+
+        if idle {
+            // Nothing has changed since the last frame.
+        } else if game_state == GameState::Dead {
+            let mut lines = morgue::get_summary(&stats, &player, map.get_depth());
+            lines.push(String::new());
+            lines.extend(run_notes.iter().flat_map(|n| n.iter().cloned()));
+            ui::render_text_screen(&mut root, "You Died", &lines, "Esc: main menu");
+            root.flush();
+        } else if game_state == GameState::Character {
+            let lines = character_sheet::get_character_sheet(&player_name, &player, &stats, sneaking);
+            ui::render_text_screen(&mut root, "Character", &lines, "Esc: close");
+            root.flush();
+        } else if game_state == GameState::History {
+            root.clear();
+            ui.render_history(&mut root);
+            root.flush();
+        } else {
+            let shown_cursor = if game_state == GameState::Targeting { Some(cursor) } else { None };
+            // Worked out on every redraw, as the view moves under the mouse when the player does.
+            ui.set_tooltip(mouse_cell.and_then(|c| describe_tile(&map, &npcs, &player, c, show_minimap)));
+            render_all(&mut root, &mut buffer_console, &mut ui, &map, &npcs, &player, Overlay{ cursor: shown_cursor, show_minimap: show_minimap });
+        }
+
+        let input = if auto_move.is_some() && game_state == GameState::Playing {
+            Some((take_auto_step(&map, &mut ui, &npcs, &mut player, &mut auto_move), game_state))
+        } else {
+            handle_input(game_state, &mut InputContext {
+                map: &mut map,
+                ui: &mut ui,
+                npcs: &mut npcs,
+                player: &mut player,
+                auto_move: &mut auto_move,
+                cursor: &mut cursor,
+                sneaking: &mut sneaking,
+                show_minimap: &mut show_minimap,
+                stats: &mut stats,
+                mouse_cell: &mut mouse_cell,
+            })
+        };
+
+        let (player_action, new_game_state) = match input {
+            Some(input) => input,
+            None => {
+                idle = true;
+                thread::sleep(Duration::from_millis(1000 / LIMIT_FPS as u64));
+                continue;
+            },
+        };
+        idle = false;
+        // Once the run is over, leaving it goes back to the title screen for good.
+        if new_game_state == GameState::Menu && (game_state == GameState::Dead || game_state == GameState::Victory) {
+            run_in_progress = false;
+        }
         game_state = new_game_state;
 
         if player_action == PlayerAction::Moved {
-            map.update_fov(player.get_position(), FOV_RADIUS);
+            map.update_fov(player.get_position(), FOV_RADIUS, fov_algorithm);
+            map.update_player_maps(player.get_position());
+
+            let loudness = match (sneaking, auto_move.is_some()) {
+                (true, _) => noise::SNEAK_LOUDNESS,
+                (false, true) => noise::RUN_LOUDNESS,
+                (false, false) => noise::WALK_LOUDNESS,
+            };
+            map.make_noise(player.get_position(), loudness);
         }
 
         match (game_state, player_action) {
             (GameState::Exit, _) => break,
             (GameState::NewMap, _) => {
-                let (new_map, units, start_coord) = map::Map::init(&unit_types);
+                let allies = npcs.drain(..)
+                    .filter(|n| n.is_ally() && n.get_hp() > 0 && n.get_ai_state() == units::AiState::Following)
+                    .collect();
+
+                let depth = map.get_depth() + 1;
+                let (new_map, units, start_coord) = map::Map::init(&unit_types, depth, stats.get_seed(), &mut spawned_uniques);
                 map = new_map;
                 npcs = units;
+                place_allies(&mut map, &mut npcs, allies, start_coord);
                 player.move_to(start_coord);
                 player.heal(255); // Just max health, whatever that is.
-                map.update_fov(start_coord, FOV_RADIUS);
+                map.update_fov(start_coord, FOV_RADIUS, fov_algorithm);
+                map.update_player_maps(start_coord);
+                auto_move = None;
                 game_state = GameState::Playing;
             }
             (GameState::Playing, PlayerAction::Moved) | (GameState::Playing, PlayerAction::Turn) => {
@@ -222,27 +947,93 @@ fn main() {
                 // Therefore we must remove the NPC from the collection before 
                 // taking its turn.
 
-                for _ in 0..npcs.len() {
-                    let mut enemy = npcs.pop_front().expect("Failed to deque NPC");
+                stats.add_turn();
+
+                // Sneaking is slower, so the NPCs get two turns for every step.
+                let npc_turns = if sneaking && player_action == PlayerAction::Moved { 2 } else { 1 };
+                let mut spawner = ai::Spawner::new(&unit_types);
+
+                let poison_damage = player.tick_poison();
+                if poison_damage > 0 {
+                    ui.add_message(&format!("You suffer {} poison damage.", poison_damage), MessageCategory::DamageTaken);
+                    if player.get_hp() == 0 {
+                        kill_player(&mut map, &mut ui, &player, &mut stats, "poison");
+                        game_state = GameState::Dead;
+                        continue;
+                    } else if !player.is_poisoned() {
+                        ui.add_message("The poison wears off.", MessageCategory::Flavour);
+                    }
+                }
+
+                for _ in 0..npc_turns {
+                    map.propagate_noises();
+                    let mut claimed_tiles = HashSet::new();
+
+                    for _ in 0..npcs.len() {
+                        let mut enemy = npcs.pop_front().expect("Failed to deque NPC");
                     
-                    if enemy.get_hp() > 0 {
-                        enemy.take_turn(&map, &mut ui, &npcs, &mut player);
+                        if enemy.get_hp() > 0 {
+                            enemy.take_turn(&mut ai::TurnContext {
+                                map: &mut map,
+                                ui: &mut ui,
+                                npcs: &mut npcs,
+                                player: &mut player,
+                                spawner: &mut spawner,
+                                claimed_tiles: &mut claimed_tiles,
+                            });
+
+                            if player.get_hp() == 0 {
+                                kill_player(&mut map, &mut ui, &player, &mut stats, enemy.get_name());
+                                game_state = GameState::Dead;
+                                // The killer still belongs in the morgue file's map.
+                                npcs.push_back(enemy);
+                                break;
+                            }
+                        }
 
-                        if player.get_hp() == 0 {
-                            ui.add_message("You died!", tcod::colors::LIGHT_RED);
-                            let corpse = item::Item::new(&player.get_name(), player.get_glyph(), tcod::colors::DARK_RED, player.get_position());
+                        // Poison can kill a unit at the start of its own turn.
+                        if enemy.get_hp() > 0 {
+                            // Must re-add the enemy to the NPC list, or it'll be lost.
+                            npcs.push_back(enemy);
+                        } else {
+                            let corpse = item::Item::new_corpse(enemy.get_name(), enemy.get_glyph(), enemy.get_position());
+
+                            map.remove_occupant(&enemy);
                             map.place_item(corpse);
-                            game_state = GameState::Dead;
-                            break;
+                            // This is the only report of a death, so fights out of sight stay hidden.
+                            if map.can_see_unit(enemy.get_position(), player.get_position()) {
+                                ui.add_message(&format!("{} is dead!", enemy.get_name()), MessageCategory::Combat);
+                            }
+
+                            if std::ptr::eq(enemy.get_unit_type(), unit_types.get_boss()) {
+                                ui.add_message(&format!("With {} dead, the dungeon falls silent. You are victorious!", enemy.get_name()), MessageCategory::System);
+                                game_state = GameState::Victory;
+                            }
+
+                            // Without their leader, the rest of the pack loses its nerve.
+                            if enemy.is_pack_leader() {
+                                let pack = enemy.get_pack();
+                                let mut survivors = 0;
+                                for npc in npcs.iter_mut().filter(|n| n.get_pack() == pack && n.get_hp() > 0) {
+                                    npc.break_morale();
+                                    survivors += 1;
+                                }
+                                if survivors > 0 && map.can_see_unit(enemy.get_position(), player.get_position()) {
+                                    ui.add_message("The rest of the pack scatters!", MessageCategory::Combat);
+                                }
+                            }
+
+                            // The run is over, so nothing else gets a turn.
+                            if game_state == GameState::Victory {
+                                break;
+                            }
                         }
+                    }
 
-                        // Must re-add the enemy to the NPC list, or it'll be lost.
-                        npcs.push_back(enemy);
-                    } else {
-                        let corpse = item::Item::new(&enemy.get_name(), enemy.get_glyph(), tcod::colors::DARK_RED, enemy.get_position());
+                    npcs.extend(spawner.take_spawned());
 
-                        map.place_item(corpse);
-                        ui.add_message(&format!("{} is dead!", enemy.get_name()), tcod::colors::WHITE);
+                    if game_state == GameState::Dead || game_state == GameState::Victory {
+                        break;
                     }
                 }
             }
@@ -1,18 +1,27 @@
 use std;
+use std::cmp::{max, min};
 use std::ops::Range;
-use std::collections::{HashSet, VecDeque};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use rand;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 use tcod;
 use tcod::console::Console;
 use tcod::Color;
 
 use traits::{Position, Renderable};
-use unit_type::UnitTypeLists;
-use units::Unit;
+use unit_type::{UnitType, UnitTypeLists};
+use units::{Unit, AiState};
 use item::Item;
+use fov;
+use fov::FovAlgorithm;
+use light::LightSource;
+use pathfinding::PathGrid;
+use dijkstra::DijkstraMap;
+use noise;
+use noise::Noise;
 
 use point::Point;
 use rectangle::Rectangle;
@@ -25,7 +34,6 @@ const MAP_MIN_HEIGHT: u8 = SCREEN_HEIGHT;
 pub const MAP_MAX_WIDTH: u8 = std::u8::MAX;
 pub const MAP_MAX_HEIGHT: u8 = std::u8::MAX;
 
-const ROOM_MAX_MONSTERS: u8 = 3;
 // Values for I/N chance of generating monsters for a room.
 const ROOM_CHANCE_OF_MONSTERS_I: u32 = 2;
 const ROOM_CHANCE_OF_MONSTERS_N: u32 = 5;
@@ -36,10 +44,33 @@ const ROOM_MIN_SIZE: u8 = 6;
 // previous max room count of 30.
 // This should provide a similar room densiter for each map.
 const ROOM_PER_TILE: u8 = 120;
+// The boss's room on the final level.
+const ARENA_WIDTH: u8 = 20;
+const ARENA_HEIGHT: u8 = 14;
+// Values for I/N chance of a room having a sconce on its wall.
+const ROOM_CHANCE_OF_SCONCE_I: u32 = 1;
+const ROOM_CHANCE_OF_SCONCE_N: u32 = 3;
+const SCONCE_RADIUS: u8 = 5;
+const SCONCE_COLOR: Color = Color{r: 255, g: 150, b: 60};
+
+// The average brightness a tile needs before things on it can be seen.
+const MIN_LIGHT_LEVEL: u16 = 40;
 
 const ERR_MSG_TUNNEL: &str = "Failed to create tunnel.";
 const ERR_MSG_ROOM: &str = "Failed to create room.";
 const ERR_MSG_ROOM_CMP: &str = "Error comparing rooms.";
+const ERR_MSG_STAIRS: &str = "Failed to place stairs.";
+const ERR_MSG_PACK: &str = "Unknown pack member type.";
+const ERR_MSG_ARENA: &str = "Failed to place the boss arena.";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExploreStep {
+    Step(Point<i16>),
+    // There's more to explore, but a unit is in the way.
+    Blocked,
+    // Everything that can be reached has been explored.
+    Finished,
+}
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum CanMoveResponse {
@@ -51,6 +82,7 @@ pub enum CanMoveResponse {
 pub enum TileType {
     Floor,
     Wall,
+    StairsDown,
 }
 
 impl TileType {
@@ -58,6 +90,7 @@ impl TileType {
         match self {
             TileType::Floor => Color::new(50, 50, 150),
             TileType::Wall => Color::new(0, 0, 100),
+            TileType::StairsDown => Color::new(100, 100, 200),
         }
     }
 
@@ -65,6 +98,7 @@ impl TileType {
         match self {
             TileType::Floor => Color::new(200, 180, 50),
             TileType::Wall => Color::new(130, 110, 50),
+            TileType::StairsDown => Color::new(255, 255, 255),
         }
     }
 
@@ -72,6 +106,7 @@ impl TileType {
         match self {
             TileType::Floor => '.',
             TileType::Wall => '#',
+            TileType::StairsDown => '>',
         }
     }
 
@@ -79,6 +114,7 @@ impl TileType {
         match self {
             TileType::Floor => false,
             TileType::Wall => true,
+            TileType::StairsDown => false,
         }
     }
 
@@ -86,6 +122,7 @@ impl TileType {
         match self {
             TileType::Floor => false,
             TileType::Wall => true,
+            TileType::StairsDown => false,
         }
     }
 }
@@ -96,6 +133,10 @@ pub struct Tile {
     tile_type: TileType,
     is_explored: bool,
     is_visible: bool,
+    // Light from things that never move, like sconces, is only calculated
+    // once. Everything else is recalculated each turn.
+    static_light: Color,
+    dynamic_light: Color,
 }
 
 impl Position for Tile {
@@ -112,11 +153,11 @@ impl Renderable for Tile {
     fn get_color(&self) -> Color {
         if !self.is_explored {
             tcod::colors::BLACK
-        } else {        
-            match self.is_visible {
-                true => self.tile_type.get_color_visible(),
-                false => self.tile_type.get_color_not_visible(),
-            }
+        } else if self.is_visible && self.is_lit() {
+            self.tile_type.get_color_visible() * self.get_light()
+        } else {
+            // Tiles in view that aren't lit are drawn the same as remembered ones.
+            self.tile_type.get_color_not_visible()
         }
     }
     fn get_glyph(&self) -> char {
@@ -131,8 +172,19 @@ impl Tile {
             tile_type: tile_type,
             is_explored: false,
             is_visible: false,
+            static_light: tcod::colors::BLACK,
+            dynamic_light: tcod::colors::BLACK,
         }
     }
+
+    fn get_light(&self) -> Color {
+        self.static_light + self.dynamic_light
+    }
+
+    fn is_lit(&self) -> bool {
+        let Color{r, g, b} = self.get_light();
+        (r as u16 + g as u16 + b as u16) / 3 >= MIN_LIGHT_LEVEL
+    }
 }
 
 
@@ -141,40 +193,93 @@ pub struct Map {
     height: u8,
     tile_map: Vec<Tile>,
     items: Vec<Item>,
+    room_centres: Vec<Point<i16>>,
+    stairs: Point<i16>,
     fov_map: tcod::map::Map,
+    // Indices into tile_map of the tiles currently in view.
+    visible_tiles: Vec<usize>,
+    // Indices into tile_map of the tiles with dynamic light.
+    lit_tiles: Vec<usize>,
+    path_grid: PathGrid,
+    player_pos: Point<i16>,
+    // Shared by all units for moving towards or away from the player. They're
+    // only built when a unit first needs them after the player moves, as most
+    // turns nothing does.
+    player_map: RefCell<Option<DijkstraMap>>,
+    flee_map: RefCell<Option<DijkstraMap>>,
+    // Leads to the nearest unexplored tile. Only rebuilt once more of the map
+    // has been explored.
+    explore_map: RefCell<Option<DijkstraMap>>,
+    // Noises made this turn, which will be heard next turn.
+    pending_noises: Vec<Noise>,
+    // The loudest noise heard at each tile, with the volume it was heard at.
+    heard_noises: HashMap<Point<i16>, Noise>,
+    next_pack_id: u32,
+    // How many levels down the map is, starting at 1.
+    depth: u8,
 }
 
 // Init and building.
 impl Map {
-    pub fn init<'a>(unit_types: &'a UnitTypeLists) -> (Map, VecDeque<Unit<'a>>, Point<i16>) {
-        let mut rng = rand::thread_rng();
+    // Unique units placed on the map are added to spawned_uniques, and won't
+    // be placed again. The same seed and depth always build the same level,
+    // as long as the same uniques have been placed before it.
+    pub fn init<'a>(unit_types: &'a UnitTypeLists, depth: u8, seed: u32, spawned_uniques: &mut HashSet<String>) -> (Map, VecDeque<Unit<'a>>, Point<i16>) {
+        let mut rng: rand::StdRng = SeedableRng::from_seed(&[seed as usize, depth as usize][..]);
 
         let map_width = rng.gen_range(MAP_MIN_WIDTH, MAP_MAX_WIDTH);
         let map_height = rng.gen_range(MAP_MIN_HEIGHT, MAP_MAX_HEIGHT);
 
-        let mut map = vec![];
-        for y in 0..map_height {
-            for x in 0..map_width {
-                map.push( Tile::new(Point{x: x as i16, y: y as i16}, TileType::Wall) );
-            }
-        }
+        let mut map = Map::new_solid(map_width, map_height, depth);
 
-        let mut map = Map {
-            width: map_width,
-            height: map_height,
-            tile_map: map,
-            items: vec![],
-            fov_map: tcod::map::Map::new(map_width as i32, map_height as i32),
+        let mut npcs = VecDeque::new();
+
+        // The final level has the boss's arena instead of stairs. It's placed
+        // first to make sure there's room for it.
+        let arena = if depth >= unit_types.get_final_depth() {
+            Some(map.build_arena(&mut rng))
+        } else {
+            None
         };
 
-        let mut npcs = VecDeque::new();
+        let (mut rooms, player_start) = map.build_rooms(&mut rng, arena.as_ref());
+
+        if let Some(arena) = arena {
+            // The arena is joined up like any other room, but is left out of
+            // the rest of the room handling.
+            rooms.push(arena);
+            map.build_coridoors(&rooms, &mut rng);
+            let arena = rooms.pop().expect(ERR_MSG_ARENA);
+
+            // The boss may already have turned up somewhere else.
+            let boss = unit_types.get_boss();
+            if !spawned_uniques.contains(boss.get_name()) {
+                let boss_state = AiState::Staying(arena.centre());
+                map.place_group(&arena, boss, boss_state, &unit_types, &mut npcs, &mut rng);
+                spawned_uniques.insert(boss.get_name().into());
+            }
+            map.add_static_light(&LightSource::new(arena.centre(), SCONCE_COLOR, ARENA_WIDTH / 2));
+        } else {
+            map.build_coridoors(&rooms, &mut rng);
 
-        let (rooms, player_start) = map.build_rooms(&mut rng);
-        map.build_coridoors(&rooms, &mut rng);
+            // The player starts in the first room, so put the way down in the last.
+            if let Some(room) = rooms.last() {
+                map.stairs = room.centre();
+                map.set_tile_type(map.stairs, TileType::StairsDown).expect(ERR_MSG_STAIRS);
+            }
+        }
+
+        map.room_centres = rooms.iter().map(|r| r.centre()).collect();
 
         for room in rooms {
             if rng.gen_range(0, ROOM_CHANCE_OF_MONSTERS_N) < ROOM_CHANCE_OF_MONSTERS_I {
-                map.place_npcs(&room, &unit_types, &mut npcs, &mut rng);
+                map.place_npcs(&room, &unit_types, spawned_uniques, &mut npcs, &mut rng);
+            }
+
+            if rng.gen_range(0, ROOM_CHANCE_OF_SCONCE_N) < ROOM_CHANCE_OF_SCONCE_I {
+                // Hang it on the middle of the top wall.
+                let pos = Point{x: room.centre().x, y: room.top_left.y};
+                map.add_static_light(&LightSource::new(pos, SCONCE_COLOR, SCONCE_RADIUS));
             }
         }
 
@@ -185,15 +290,105 @@ impl Map {
 
 
 
-    fn place_npcs<'a>(&mut self, room: &Rectangle, units: &'a UnitTypeLists, npc_list: &mut VecDeque<Unit<'a>>, rng: &mut rand::ThreadRng) {
-        let max_monsters = rng.gen_range(0, ROOM_MAX_MONSTERS);
+    // A map of nothing but wall, ready to be carved out.
+    fn new_solid(width: u8, height: u8, depth: u8) -> Map {
+        let mut tiles = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                tiles.push( Tile::new(Point{x: x as i16, y: y as i16}, TileType::Wall) );
+            }
+        }
+
+        Map {
+            width: width,
+            height: height,
+            tile_map: tiles,
+            items: vec![],
+            room_centres: vec![],
+            stairs: Point{x: 0, y: 0},
+            fov_map: tcod::map::Map::new(width as i32, height as i32),
+            visible_tiles: vec![],
+            lit_tiles: vec![],
+            path_grid: PathGrid::new(width, height),
+            player_pos: Point{x: 0, y: 0},
+            player_map: RefCell::new(None),
+            flee_map: RefCell::new(None),
+            explore_map: RefCell::new(None),
+            pending_noises: vec![],
+            heard_noises: HashMap::new(),
+            next_pack_id: 0,
+            depth: depth,
+        }
+    }
+
+    fn place_npcs<'a>(&mut self, room: &Rectangle, units: &'a UnitTypeLists, spawned_uniques: &mut HashSet<String>, npc_list: &mut VecDeque<Unit<'a>>, rng: &mut rand::StdRng) {
+        let max_monsters = units.get_room_max_monsters(self.depth);
+        if max_monsters == 0 {
+            return;
+        }
+
+        for _ in 0..rng.gen_range(0, max_monsters) {
+            let monster_type = match units.get_random_type(rng, self.depth, spawned_uniques) {
+                Some(monster_type) => monster_type,
+                None => return,
+            };
+            // Half of the monsters start off awake, moving between rooms.
+            let state = if rng.gen_weighted_bool(2) { AiState::Wandering(room.centre()) } else { AiState::Asleep };
+
+            if monster_type.is_unique() {
+                spawned_uniques.insert(monster_type.get_name().into());
+            }
+            self.place_group(room, monster_type, state, units, npc_list, rng);
+        }
+    }
+
+    // Places a unit, along with its pack if it leads one.
+    fn place_group<'a>(&mut self, room: &Rectangle, unit_type: &'a UnitType, state: AiState, units: &'a UnitTypeLists, npc_list: &mut VecDeque<Unit<'a>>, rng: &mut rand::StdRng) {
+        // Bosses start in the middle of their room.
+        let position = match state {
+            AiState::Staying(pos) => pos,
+            _ => room.get_random_position(rng),
+        };
+
+        if unit_type.get_pack().is_empty() {
+            self.add_npc(Unit::new(position, unit_type), state, npc_list);
+            return;
+        }
+
+        let pack_id = self.next_pack_id;
+        self.next_pack_id += 1;
+
+        let mut leader = Unit::new(position, unit_type);
+        leader.set_pack(pack_id, true);
+        self.add_npc(leader, state, npc_list);
+
+        for member in unit_type.get_pack() {
+            let member_type = units.get_type_by_name(&member.name).expect(ERR_MSG_PACK);
 
-        for _ in 0..max_monsters {
-            let position = room.get_random_position(rng);
-            let monster_type = units.get_random_type(rng);
+            for _ in 0..rng.gen_range(member.min, member.max + 1) {
+                let mut follower = Unit::new(room.get_random_position(rng), member_type);
+                follower.set_pack(pack_id, false);
 
-            let monster = Unit::new(position, monster_type);
-            npc_list.push_back(monster);
+                // Followers guard the room with their leader, each from where they start.
+                let follower_state = match state {
+                    AiState::Staying(_) => AiState::Staying(follower.get_position()),
+                    _ => state,
+                };
+                self.add_npc(follower, follower_state, npc_list);
+            }
+        }
+    }
+
+    fn add_npc<'a>(&mut self, mut npc: Unit<'a>, state: AiState, npc_list: &mut VecDeque<Unit<'a>>) {
+        npc.set_ai_state(state);
+        self.add_occupant(&npc);
+        npc_list.push_back(npc);
+    }
+
+    fn add_static_light(&mut self, light: &LightSource) {
+        for (idx, color) in self.get_lit_tiles(light) {
+            let tile = &mut self.tile_map[idx];
+            tile.static_light = tile.static_light + color;
         }
     }
 
@@ -201,7 +396,22 @@ impl Map {
         self.items.push(item);
     }
 
-    fn build_rooms(&mut self, rng: &mut rand::ThreadRng) -> (Vec<Rectangle>, Point<i16>) {
+    // Carves out a large room somewhere on the map.
+    fn build_arena(&mut self, rng: &mut rand::StdRng) -> Rectangle {
+        let arena = Rectangle::new(
+            Point {
+                x: rng.gen_range(0, self.width as i16 - ARENA_WIDTH as i16 - 1),
+                y: rng.gen_range(0, self.height as i16 - ARENA_HEIGHT as i16 - 1)
+            },
+            (ARENA_WIDTH, ARENA_HEIGHT)
+        );
+
+        self.create_room(&arena).expect(ERR_MSG_ARENA);
+        arena
+    }
+
+    // Rooms won't be placed over the arena, if there is one.
+    fn build_rooms(&mut self, rng: &mut rand::StdRng, arena: Option<&Rectangle>) -> (Vec<Rectangle>, Point<i16>) {
         let mut rooms = vec![];
         let mut player_start = Point{x:0, y:0};
 
@@ -220,7 +430,7 @@ impl Map {
                 (width, height)
             );
 
-            if !rooms.iter().any(|r: &Rectangle| r.is_intersecting(&room)) {
+            if !rooms.iter().chain(arena).any(|r: &Rectangle| r.is_intersecting(&room)) {
                 self.create_room(&room).expect(ERR_MSG_ROOM);
 
                 if rooms.len() == 0 {
@@ -234,7 +444,7 @@ impl Map {
         (rooms, player_start)
     }
 
-    fn build_coridoors(&mut self, rooms: &Vec<Rectangle>, rng: &mut rand::ThreadRng) {
+    fn build_coridoors(&mut self, rooms: &Vec<Rectangle>, rng: &mut rand::StdRng) {
         let mut sorted_rooms: Vec<_> = rooms.iter().map(|r| r).collect();
         let mut connected_rooms = HashSet::new();
         
@@ -318,32 +528,86 @@ impl Map {
 
 // Rendering
 impl Map {
-    pub fn render_map<T: Console>(&self, cons: &mut T) {
-        for tile in self.tile_map.iter() {
-            tile.render(cons);
+    // Only the tiles and items inside the view port are drawn, so the cost of
+    // a frame depends on the screen size rather than the map size.
+    pub fn render_map<T: Console>(&self, cons: &mut T, view_port: &Rectangle) {
+        let offset = view_port.top_left;
+
+        let left = max(view_port.top_left.x, 0) as usize;
+        let top = max(view_port.top_left.y, 0) as usize;
+        let right = min(view_port.bottom_right.x, self.width as i16);
+        let bottom = min(view_port.bottom_right.y, self.height as i16);
+
+        for y in top..max(bottom, 0) as usize {
+            let row_start = y * self.width as usize;
+
+            for x in left..max(right, 0) as usize {
+                self.tile_map[row_start + x].render(cons, offset);
+            }
         }
 
-        for item in self.items.iter() {
-            item.render(cons);
+        for item in self.items.iter().filter(|i| view_port.contains_point(i.get_position())) {
+            item.render(cons, offset);
         }
     }
 }
 
 // Queries
 impl Map {
+    // The area around the centre as plain text, with one line per row. Only
+    // explored tiles are shown, with items and then the given units drawn
+    // over them.
+    pub fn dump_area<I>(&self, centre: Point<i16>, radius: i16, units: I) -> Vec<String>
+        where I: IntoIterator<Item=(Point<i16>, char)>
+    {
+        let size = radius * 2 + 1;
+        let top_left = centre - Point{x: radius, y: radius};
+        let mut rows = vec![vec![' '; size as usize]; size as usize];
+
+        let mut draw = |pos: Point<i16>, glyph: char| {
+            let Point{x, y} = pos - top_left;
+            if x >= 0 && x < size && y >= 0 && y < size {
+                rows[y as usize][x as usize] = glyph;
+            }
+        };
+
+        for y in top_left.y..top_left.y + size {
+            for x in top_left.x..top_left.x + size {
+                match self.get_tile(Point{x: x, y: y}) {
+                    Ok(tile) if tile.is_explored => draw(tile.position, tile.get_glyph()),
+                    _ => {},
+                }
+            }
+        }
+
+        for item in self.items.iter() {
+            draw(item.get_position(), item.get_glyph());
+        }
+
+        for (pos, glyph) in units {
+            draw(pos, glyph);
+        }
+
+        rows.into_iter().map(|r| r.into_iter().collect::<String>().trim_end().to_string()).collect()
+    }
+
     pub fn point_in_map(&self, Point{x,y}: Point<i16>) -> bool {
         x >= 0 && x < self.width as i16 && y >= 0 && y < self.height as i16
     }
 
-    pub fn get_tile_type(&self, pos: Point<i16>) -> Result<TileType,()> {
+    fn get_tile(&self, pos: Point<i16>) -> Result<&Tile,()> {
         if !self.point_in_map(pos) {
             Err(())
         } else {
             let Point{x, y} = pos;
-            Ok(self.tile_map[y as usize * self.width as usize + x as usize].tile_type)
+            Ok(&self.tile_map[y as usize * self.width as usize + x as usize])
         }
     }
 
+    pub fn get_tile_type(&self, pos: Point<i16>) -> Result<TileType,()> {
+        self.get_tile(pos).map(|t| t.tile_type)
+    }
+
     pub fn can_move_to(&self, pos: Point<i16>) -> CanMoveResponse {
         match self.get_tile_type(pos) {
             Ok(tile) if tile.blocks_move() => CanMoveResponse::Scenery,
@@ -351,12 +615,103 @@ impl Map {
         }
     }
 
+    pub fn get_depth(&self) -> u8 {
+        self.depth
+    }
+
     pub fn get_map_size(&self) -> (u8, u8) {
         (self.width, self.height)
     }
 
-    pub fn point_in_fov(&self, Point{x,y}: Point<i16>) -> bool {
-        self.fov_map.is_in_fov(x as i32, y as i32)
+    pub fn point_is_lit(&self, pos: Point<i16>) -> bool {
+        self.get_tile(pos).is_ok_and(|t| t.is_lit())
+    }
+
+    // Units standing in the dark can only be seen when right next to the viewer.
+    pub fn can_see_unit(&self, unit_pos: Point<i16>, viewer_pos: Point<i16>) -> bool {
+        self.point_in_fov(unit_pos)
+            && (self.point_is_lit(unit_pos) || (unit_pos - viewer_pos).radius() < 2.0)
+    }
+
+    pub fn get_heard_noise(&self, pos: Point<i16>) -> Option<Noise> {
+        self.heard_noises.get(&pos).cloned()
+    }
+
+    pub fn has_line_of_sight(&self, from: Point<i16>, to: Point<i16>, radius: u8) -> bool {
+        if (to - from).sqr_radius() > radius as f64 * radius as f64 {
+            return false;
+        }
+
+        fov::compute_fov(from, radius, |p| self.blocks_sight(p)).contains(&to)
+    }
+
+    // Everything a unit at `from` can see, for checking many targets at once.
+    pub fn get_visible_from(&self, from: Point<i16>, radius: u8) -> HashSet<Point<i16>> {
+        fov::compute_fov(from, radius, |p| self.blocks_sight(p)).into_iter().collect()
+    }
+
+    pub fn get_item_names_at(&self, pos: Point<i16>) -> Vec<&str> {
+        self.items.iter().filter(|i| i.get_position() == pos).map(|i| i.get_name()).collect()
+    }
+
+    // The ids of the items in view, other than corpses.
+    pub fn get_visible_item_ids(&self) -> HashSet<usize> {
+        self.items.iter()
+            .filter(|i| !i.is_corpse() && self.point_in_fov(i.get_position()))
+            .map(|i| i.get_id())
+            .collect()
+    }
+
+    // Finds an empty tile next to the given position, for placing a new unit.
+    // The player isn't tracked by the path grid, so their position must be given.
+    pub fn get_free_neighbour(&self, pos: Point<i16>, player_pos: Point<i16>) -> Option<Point<i16>> {
+        let mut neighbours = vec![];
+        for y in -1..2 {
+            for x in -1..2 {
+                neighbours.push(pos + Point{x: x, y: y});
+            }
+        }
+
+        neighbours.into_iter().find(|&p| p != pos && p != player_pos && self.path_grid.is_walkable(p))
+    }
+
+    pub fn get_random_room_centre(&self, rng: &mut rand::ThreadRng) -> Point<i16> {
+        *rng.choose(&self.room_centres).unwrap_or(&self.stairs)
+    }
+
+    pub fn is_stairs(&self, pos: Point<i16>) -> bool {
+        self.get_tile(pos).is_ok_and(|t| t.tile_type == TileType::StairsDown)
+    }
+
+    // The stairs are only known once they've been seen.
+    pub fn get_known_stairs(&self) -> Option<Point<i16>> {
+        match self.get_tile(self.stairs) {
+            // The final level has no stairs.
+            Ok(tile) if tile.is_explored && tile.tile_type == TileType::StairsDown => Some(self.stairs),
+            _ => None,
+        }
+    }
+
+    // Returns the index and light color of every tile the light reaches.
+    fn get_lit_tiles(&self, light: &LightSource) -> Vec<(usize, Color)> {
+        fov::compute_fov(light.position, light.radius, |p| self.blocks_sight(p))
+            .into_iter()
+            .filter(|&p| self.point_in_map(p))
+            .map(|p| (p.y as usize * self.width as usize + p.x as usize, light.get_color_at(p)))
+            .collect()
+    }
+
+    pub fn is_explored(&self, pos: Point<i16>) -> bool {
+        self.get_tile(pos).is_ok_and(|t| t.is_explored)
+    }
+
+    pub fn point_in_fov(&self, pos: Point<i16>) -> bool {
+        self.get_tile(pos).is_ok_and(|t| t.is_visible)
+    }
+
+    // Anything outside the map is treated as solid.
+    pub fn blocks_sight(&self, pos: Point<i16>) -> bool {
+        self.get_tile_type(pos).map_or(true, |t| t.blocks_sight())
     }
 
     fn build_fov_map(tiles: &Vec<Tile>, fov_map: &mut tcod::map::Map) {
@@ -366,39 +721,303 @@ impl Map {
         }
     }
 
-    pub fn get_pathfinding_map(&self) -> tcod::Map {
-        let mut path_map = tcod::Map::new(self.width as i32, self.height as i32);
-        Map::build_fov_map(&self.tile_map, &mut path_map);
-        path_map
+    pub fn get_path_grid(&self) -> &PathGrid {
+        &self.path_grid
+    }
+
+    // Only checks the terrain, not whether there's a unit in the way.
+    fn is_passable(&self, pos: Point<i16>) -> bool {
+        self.get_tile_type(pos).is_ok_and(|t| !t.blocks_move())
+    }
+
+    fn build_player_map(&self) -> DijkstraMap {
+        DijkstraMap::new(self.width, self.height, &[self.player_pos], |p| self.is_passable(p))
+    }
+
+    // The next step towards the player, ignoring other units except to not
+    // step onto them.
+    pub fn get_step_towards_player(&self, pos: Point<i16>) -> Option<Point<i16>> {
+        let mut player_map = self.player_map.borrow_mut();
+        let player_map = player_map.get_or_insert_with(|| self.build_player_map());
+        player_map.get_downhill_step(pos, |p| self.path_grid.is_walkable(p))
+    }
+
+    pub fn get_step_away_from_player(&self, pos: Point<i16>) -> Option<Point<i16>> {
+        let mut flee_map = self.flee_map.borrow_mut();
+        let flee_map = flee_map.get_or_insert_with(|| {
+            let mut player_map = self.player_map.borrow_mut();
+            player_map.get_or_insert_with(|| self.build_player_map()).inverted(|p| self.is_passable(p))
+        });
+        flee_map.get_downhill_step(pos, |p| self.path_grid.is_walkable(p))
+    }
+
+    // Returns the next step towards the nearest unexplored tile.
+    pub fn get_explore_step(&self, pos: Point<i16>) -> ExploreStep {
+        let mut explore_map = self.explore_map.borrow_mut();
+        let explore_map = explore_map.get_or_insert_with(|| {
+            let goals: Vec<_> = self.tile_map.iter()
+                .filter(|t| !t.is_explored && !t.tile_type.blocks_move())
+                .map(|t| t.get_position())
+                .collect();
+
+            DijkstraMap::new(self.width, self.height, &goals, |p| self.is_passable(p))
+        });
+
+        if !explore_map.can_reach_goal(pos) {
+            return ExploreStep::Finished;
+        }
+
+        match explore_map.get_downhill_step(pos, |p| self.path_grid.is_walkable(p)) {
+            Some(step) => ExploreStep::Step(step),
+            None => ExploreStep::Blocked,
+        }
     }
 }
 
 // Updating
 impl Map {
-    pub fn update_fov(&mut self, Point{x, y}: Point<i16>, light_radius: u8) {
-        self.fov_map.compute_fov(x as i32, y as i32, light_radius as i32, true, tcod::map::FovAlgorithm::Permissive0);
+    pub fn update_fov(&mut self, origin: Point<i16>, light_radius: u8, algorithm: FovAlgorithm) {
+        let visible_points = match algorithm {
+            FovAlgorithm::Tcod => self.compute_tcod_fov(origin, light_radius),
+            FovAlgorithm::Shadowcast => fov::compute_fov(origin, light_radius, |p| self.blocks_sight(p)),
+        };
 
         // I've opted to update the tile map here, because it doesn't make sense that
         // a function for rendering should need to mutate the object.
-        // It does make this function more expensive to run, but it won't be run
-        // too frequently.
-        for tile in self.tile_map.iter_mut() {
-            tile.is_visible = self.fov_map.is_in_fov(tile.get_x() as i32, tile.get_y() as i32);
+        // Nothing outside the light radius can be in view, so we only need to clear
+        // the tiles that were visible last time before marking the new ones.
+        for idx in self.visible_tiles.drain(..) {
+            self.tile_map[idx].is_visible = false;
+        }
 
-            if tile.is_visible {
+        for pos in visible_points {
+            if !self.point_in_map(pos) {
+                continue;
+            }
+
+            let idx = pos.y as usize * self.width as usize + pos.x as usize;
+            let tile = &mut self.tile_map[idx];
+
+            if !tile.is_explored {
                 tile.is_explored = true;
+                self.explore_map = RefCell::new(None);
+            }
+            tile.is_visible = true;
+            self.visible_tiles.push(idx);
+        }
+    }
+
+    fn compute_tcod_fov(&mut self, Point{x, y}: Point<i16>, light_radius: u8) -> Vec<Point<i16>> {
+        self.fov_map.compute_fov(x as i32, y as i32, light_radius as i32, true, tcod::map::FovAlgorithm::Permissive0);
+
+        // Only the radius' bounding box needs to be checked.
+        let radius = light_radius as i16;
+        let left = max(x - radius, 0);
+        let top = max(y - radius, 0);
+        let right = min(x + radius, self.width as i16 - 1);
+        let bottom = min(y + radius, self.height as i16 - 1);
+
+        let mut visible = vec![];
+        for tile_y in top..bottom+1 {
+            for tile_x in left..right+1 {
+                if self.fov_map.is_in_fov(tile_x as i32, tile_y as i32) {
+                    visible.push(Point{x: tile_x, y: tile_y});
+                }
+            }
+        }
+
+        visible
+    }
+
+    pub fn make_noise(&mut self, source: Point<i16>, loudness: u8) {
+        self.pending_noises.push(Noise::new(source, loudness));
+    }
+
+    // Spreads out the noises made since the last call, so units can hear them.
+    pub fn propagate_noises(&mut self) {
+        self.heard_noises.clear();
+
+        let pending = std::mem::take(&mut self.pending_noises);
+        for made in pending {
+            let heard = noise::propagate(made, |p| self.get_tile_type(p).map_or(true, |t| t.blocks_move()));
+
+            for (pos, volume) in heard {
+                let is_louder = self.heard_noises.get(&pos).is_none_or(|n| n.volume < volume);
+                if is_louder {
+                    self.heard_noises.insert(pos, Noise::new(made.source, volume));
+                }
+            }
+        }
+    }
+
+    // Only needs to be called when the player moves, as the maps don't
+    // account for other units. The maps are rebuilt the next time they're used.
+    pub fn update_player_maps(&mut self, player_pos: Point<i16>) {
+        if player_pos != self.player_pos {
+            self.player_pos = player_pos;
+            self.player_map = RefCell::new(None);
+            self.flee_map = RefCell::new(None);
+        }
+    }
+
+    pub fn update_lighting(&mut self, lights: &[LightSource]) {
+        for idx in self.lit_tiles.drain(..) {
+            self.tile_map[idx].dynamic_light = tcod::colors::BLACK;
+        }
+
+        for light in lights {
+            for (idx, color) in self.get_lit_tiles(light) {
+                let tile = &mut self.tile_map[idx];
+                tile.dynamic_light = tile.dynamic_light + color;
+                self.lit_tiles.push(idx);
             }
         }
     }
 
+    // Only blocking units are tracked, as the others don't get in the way.
+    pub fn add_occupant(&mut self, unit: &Unit) {
+        if unit.is_blocking() {
+            self.path_grid.add_occupant(unit.get_position());
+        }
+    }
+
+    pub fn remove_occupant(&mut self, unit: &Unit) {
+        if unit.is_blocking() {
+            self.path_grid.remove_occupant(unit.get_position());
+        }
+    }
+
     fn set_tile_type(&mut self, pos: Point<i16>, new_tile: TileType) -> Result<(),()> {
         if !self.point_in_map(pos) {
             Err(())
         } else {
             let Point{x,y} = pos;
             self.tile_map[y as usize * self.width as usize + x as usize].tile_type = new_tile;
+            self.path_grid.set_walkable(pos, !new_tile.blocks_move());
 
             Ok(())
         }
     }
-}
\ No newline at end of file
+}
+// Used by the tests and benchmarks, which need maps of a known size without
+// loading any data.
+#[cfg(test)]
+impl Map {
+    // An open, fully explored map with a pillar every few tiles, so there's
+    // something to block sight and movement.
+    pub fn new_test_map(width: u8, height: u8) -> Map {
+        let mut map = Map::new_solid(width, height, 1);
+        let room = Rectangle::new(Point{x: 0, y: 0}, (width - 1, height - 1));
+        map.create_room(&room).expect(ERR_MSG_ROOM);
+
+        for y in (4..height as i16 - 1).filter(|y| y % 4 == 0) {
+            for x in (4..width as i16 - 1).filter(|x| x % 4 == 0) {
+                map.set_tile_type(Point{x: x, y: y}, TileType::Wall).expect(ERR_MSG_ROOM);
+            }
+        }
+
+        for tile in map.tile_map.iter_mut() {
+            tile.is_explored = true;
+        }
+
+        Map::build_fov_map(&map.tile_map, &mut map.fov_map);
+        map
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use test::Bencher;
+
+    use tcod::console::Offscreen;
+
+    use super::*;
+    use {FOV_RADIUS, PANEL_HEIGHT};
+
+    // The view port is always the size of the screen, so this should take
+    // about as long however big the map is.
+    fn bench_render(b: &mut Bencher, width: u8, height: u8) {
+        let map = Map::new_test_map(width, height);
+        let view_port = Rectangle::new(Point{x: 0, y: 0}, (SCREEN_WIDTH, SCREEN_HEIGHT - PANEL_HEIGHT));
+        let mut cons = Offscreen::new(SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32);
+
+        b.iter(|| map.render_map(&mut cons, &view_port));
+    }
+
+    #[bench]
+    fn render_small_map(b: &mut Bencher) {
+        bench_render(b, MAP_MIN_WIDTH, MAP_MIN_HEIGHT);
+    }
+
+    #[bench]
+    fn render_large_map(b: &mut Bencher) {
+        bench_render(b, MAP_MAX_WIDTH, MAP_MAX_HEIGHT);
+    }
+
+    // The old way, for comparison: every tile and item is drawn to a console
+    // the size of the map, and the view port is blitted out of it.
+    fn bench_render_full(b: &mut Bencher, width: u8, height: u8) {
+        let map = Map::new_test_map(width, height);
+        let origin = Point{x: 0, y: 0};
+        let mut map_cons = Offscreen::new(width as i32, height as i32);
+        let mut cons = Offscreen::new(SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32);
+
+        b.iter(|| {
+            for tile in map.tile_map.iter() {
+                tile.render(&mut map_cons, origin);
+            }
+            for item in map.items.iter() {
+                item.render(&mut map_cons, origin);
+            }
+            tcod::console::blit(&map_cons, (0, 0), (SCREEN_WIDTH as i32, (SCREEN_HEIGHT - PANEL_HEIGHT) as i32), &mut cons, (0, 0), 1.0, 1.0);
+        });
+    }
+
+    #[bench]
+    fn render_small_map_full(b: &mut Bencher) {
+        bench_render_full(b, MAP_MIN_WIDTH, MAP_MIN_HEIGHT);
+    }
+
+    #[bench]
+    fn render_large_map_full(b: &mut Bencher) {
+        bench_render_full(b, MAP_MAX_WIDTH, MAP_MAX_HEIGHT);
+    }
+
+    // Only the tiles around the player are touched, so this shouldn't depend
+    // on the map size either.
+    fn bench_fov(b: &mut Bencher, algorithm: FovAlgorithm) {
+        let mut map = Map::new_test_map(MAP_MAX_WIDTH, MAP_MAX_HEIGHT);
+        let centre = Point{x: MAP_MAX_WIDTH as i16 / 2, y: MAP_MAX_HEIGHT as i16 / 2};
+
+        b.iter(|| map.update_fov(centre, FOV_RADIUS, algorithm));
+    }
+
+    #[bench]
+    fn update_fov_large_map_tcod(b: &mut Bencher) {
+        bench_fov(b, FovAlgorithm::Tcod);
+    }
+
+    #[bench]
+    fn update_fov_large_map_shadowcast(b: &mut Bencher) {
+        bench_fov(b, FovAlgorithm::Shadowcast);
+    }
+
+    // The old way, for comparison: libtcod's FOV followed by a pass over
+    // every tile in the map.
+    #[bench]
+    fn update_fov_large_map_full(b: &mut Bencher) {
+        let mut map = Map::new_test_map(MAP_MAX_WIDTH, MAP_MAX_HEIGHT);
+        let centre = Point{x: MAP_MAX_WIDTH as i16 / 2, y: MAP_MAX_HEIGHT as i16 / 2};
+
+        b.iter(|| {
+            map.fov_map.compute_fov(centre.x as i32, centre.y as i32, FOV_RADIUS as i32, true, tcod::map::FovAlgorithm::Permissive0);
+
+            for tile in map.tile_map.iter_mut() {
+                tile.is_visible = map.fov_map.is_in_fov(tile.get_x() as i32, tile.get_y() as i32);
+                if tile.is_visible {
+                    tile.is_explored = true;
+                }
+            }
+        });
+    }
+}
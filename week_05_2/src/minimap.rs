@@ -0,0 +1,121 @@
+use tcod;
+use tcod::{BackgroundFlag, TextAlignment};
+use tcod::console::Console;
+use tcod::colors::Color;
+
+use traits::Position;
+use point::Point;
+use rectangle::Rectangle;
+use map::{Map, TileType};
+use units::Unit;
+
+use std::cmp::max;
+use std::collections::VecDeque;
+
+// The map is scaled down by at least this much, and further if it still
+// wouldn't fit on the console.
+const MIN_SCALE: i16 = 2;
+// Space left around the minimap for its frame.
+const BORDER: i32 = 1;
+
+const FLOOR_COLOR: Color = Color{r: 70, g: 70, b: 110};
+const WALL_COLOR: Color = Color{r: 25, g: 25, b: 60};
+
+// How many tiles each cell covers, and which cells show the map.
+struct Layout {
+    scale: i16,
+    width: i16,
+    height: i16,
+    left: i32,
+    top: i32,
+}
+
+fn get_layout(map: &Map, cons_width: i32, cons_height: i32) -> Layout {
+    let (map_width, map_height) = map.get_map_size();
+    let max_width = cons_width - BORDER * 2;
+    let max_height = cons_height - BORDER * 2 - 1;
+
+    // Rounded up, so the whole map always fits.
+    let fit = |size: u8, space: i32| (size as i32 + space - 1) / space;
+    let scale = max(MIN_SCALE, max(fit(map_width, max_width), fit(map_height, max_height)) as i16);
+
+    let width = (map_width as i16 + scale - 1) / scale;
+    let height = (map_height as i16 + scale - 1) / scale;
+
+    Layout {
+        scale: scale,
+        width: width,
+        height: height,
+        left: (cons_width - width as i32) / 2,
+        top: (cons_height - height as i32) / 2,
+    }
+}
+
+// The cells the minimap covers on a console of the given size, including its
+// frame and title.
+pub fn get_minimap_rect(map: &Map, cons_width: i32, cons_height: i32) -> Rectangle {
+    let Layout{width, height, left, top, ..} = get_layout(map, cons_width, cons_height);
+    let top_left = Point{x: (left - BORDER) as i16, y: (top - BORDER - 1) as i16};
+    Rectangle::new(top_left, ((width as i32 + BORDER * 2) as u8, (height as i32 + BORDER * 2 + 1) as u8))
+}
+
+// Draws the explored part of the map scaled down in the middle of the
+// console, with each cell covering a square of tiles.
+pub fn render_minimap<T: Console>(cons: &mut T, map: &Map, npcs: &VecDeque<Unit>, player: &Unit) {
+    let Layout{scale, width, height, left, top} = get_layout(map, cons.width(), cons.height());
+
+    cons.set_default_background(tcod::colors::BLACK);
+    cons.rect(left - BORDER, top - BORDER - 1, width as i32 + BORDER * 2, height as i32 + BORDER * 2 + 1, true, BackgroundFlag::Set);
+    cons.set_default_foreground(tcod::colors::WHITE);
+    cons.print_ex(left + width as i32 / 2, top - BORDER - 1, BackgroundFlag::None, TextAlignment::Center, format!("Depth {} (1:{})", map.get_depth(), scale));
+
+    for cy in 0..height {
+        for cx in 0..width {
+            if let Some(color) = get_cell_color(map, Point{x: cx * scale, y: cy * scale}, scale) {
+                cons.set_char_background(left + cx as i32, top + cy as i32, color, BackgroundFlag::Set);
+            }
+        }
+    }
+
+    let mut mark = |pos: Point<i16>, glyph: char, color: Color| {
+        let x = left + (pos.x / scale) as i32;
+        let y = top + (pos.y / scale) as i32;
+        cons.set_default_foreground(color);
+        cons.put_char(x, y, glyph, BackgroundFlag::None);
+    };
+
+    if let Some(stairs) = map.get_known_stairs() {
+        mark(stairs, '>', tcod::colors::WHITE);
+    }
+
+    for npc in npcs.iter().filter(|n| n.get_hp() > 0 && map.can_see_unit(n.get_position(), player.get_position())) {
+        let color = if npc.is_ally() { tcod::colors::LIGHT_GREEN } else { tcod::colors::LIGHT_RED };
+        mark(npc.get_position(), '*', color);
+    }
+
+    // The player goes on top, so they can always be found.
+    mark(player.get_position(), '@', tcod::colors::YELLOW);
+}
+
+// A cell with any explored floor is shown as floor, so corridors don't
+// vanish between walls.
+fn get_cell_color(map: &Map, top_left: Point<i16>, scale: i16) -> Option<Color> {
+    let mut color = None;
+
+    for y in top_left.y..top_left.y + scale {
+        for x in top_left.x..top_left.x + scale {
+            let pos = Point{x: x, y: y};
+            if !map.is_explored(pos) {
+                continue;
+            }
+
+            match map.get_tile_type(pos) {
+                Ok(TileType::Wall) => color = color.or(Some(WALL_COLOR)),
+                Ok(_) => return Some(FLOOR_COLOR),
+                Err(_) => {},
+            }
+        }
+    }
+
+    color
+}
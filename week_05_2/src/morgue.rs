@@ -0,0 +1,118 @@
+use traits::{Position, Renderable};
+use map::Map;
+use units::Unit;
+use ui::UI;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MORGUE_DIR: &str = "morgue";
+// How many tiles either side of the player are included in the map dump.
+const MAP_DUMP_RADIUS: i16 = 15;
+
+// Everything about a run that isn't kept anywhere else.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    turns: u32,
+    // Kills made by the player, by unit type name. Sorted, so the summary
+    // always lists them in the same order.
+    kills: BTreeMap<String, u32>,
+    cause_of_death: Option<String>,
+    // Used to build each level of the dungeon.
+    seed: u32,
+}
+
+impl RunStats {
+    pub fn new(seed: u32) -> RunStats {
+        RunStats {
+            seed: seed,
+            ..RunStats::default()
+        }
+    }
+
+    pub fn add_turn(&mut self) {
+        self.turns += 1;
+    }
+
+    pub fn add_kill(&mut self, name: &str) {
+        *self.kills.entry(name.into()).or_insert(0) += 1;
+    }
+
+    pub fn set_cause_of_death(&mut self, cause: &str) {
+        self.cause_of_death = Some(cause.into());
+    }
+
+    pub fn get_turns(&self) -> u32 {
+        self.turns
+    }
+
+    pub fn get_seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn get_kill_count(&self) -> u32 {
+        self.kills.values().sum()
+    }
+
+    pub fn get_cause_of_death(&self) -> Option<&str> {
+        self.cause_of_death.as_deref()
+    }
+}
+
+// The lines shown on the death screen, which also start the morgue file.
+pub fn get_summary(stats: &RunStats, player: &Unit, depth: u8) -> Vec<String> {
+    let unit_type = player.get_unit_type();
+    let cause = stats.get_cause_of_death().unwrap_or("unknown causes");
+
+    let mut lines = vec![
+        format!("{} was killed by {} on depth {}.", player.get_name(), cause, depth),
+        format!("Survived for {} turns.", stats.turns),
+        format!("Dungeon seed: {}", stats.seed),
+        String::new(),
+        format!("Level: {}   HP: {}/{}   Attack: {}   Defence: {}", player.get_level(), player.get_hp(), unit_type.get_max_hp(), player.get_attack(), player.get_defence()),
+        String::new(),
+        format!("Kills: {}", stats.get_kill_count()),
+    ];
+
+    lines.extend(stats.kills.iter().map(|(name, count)| format!("    {:<24} {:>3}", name, count)));
+    lines
+}
+
+// Writes the summary, the message history and the area around the player
+// to a new file in the morgue directory, returning its path. Only as many
+// messages as the history keeps make it into the file.
+pub fn write_morgue(stats: &RunStats, player: &Unit, map: &Map, npcs: &VecDeque<Unit>, ui: &UI) -> io::Result<PathBuf> {
+    fs::create_dir_all(MORGUE_DIR)?;
+
+    // Seconds since the epoch keeps the files in order and unique enough
+    // for one player.
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = Path::new(MORGUE_DIR).join(format!("morgue-{}.txt", timestamp));
+    let mut file = File::create(&path)?;
+
+    for line in get_summary(stats, player, map.get_depth()) {
+        writeln!(file, "{}", line)?;
+    }
+
+    writeln!(file, "\nFinal surroundings:")?;
+    let units = npcs.iter()
+        .filter(|n| n.get_hp() > 0 && map.can_see_unit(n.get_position(), player.get_position()))
+        .map(|n| (n.get_position(), n.get_glyph()))
+        .chain(Some((player.get_position(), player.get_glyph())));
+    for line in map.dump_area(player.get_position(), MAP_DUMP_RADIUS, units) {
+        writeln!(file, "{}", line)?;
+    }
+
+    let messages = ui.get_message_log();
+    writeln!(file, "\nLast {} messages:", messages.len())?;
+    for message in messages {
+        writeln!(file, "{}", message)?;
+    }
+
+    Ok(path)
+}
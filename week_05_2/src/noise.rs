@@ -0,0 +1,73 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use point::Point;
+
+pub const COMBAT_LOUDNESS: u8 = 12;
+pub const RUN_LOUDNESS: u8 = 5;
+pub const WALK_LOUDNESS: u8 = 3;
+pub const SNEAK_LOUDNESS: u8 = 2;
+
+// How much quieter a noise gets passing through a tile. Walls muffle sound a
+// lot more than open floor.
+const FLOOR_ATTENUATION: u8 = 1;
+const WALL_ATTENUATION: u8 = 4;
+
+const NEIGHBOURS: [Point<i16>; 4] = [
+    Point{x: 0, y: -1},
+    Point{x: 0, y: 1},
+    Point{x: -1, y: 0},
+    Point{x: 1, y: 0},
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Noise {
+    pub source: Point<i16>,
+    pub volume: u8,
+}
+
+impl Noise {
+    pub fn new(source: Point<i16>, volume: u8) -> Noise {
+        Noise {
+            source: source,
+            volume: volume,
+        }
+    }
+}
+
+// Spreads a noise out from its source, returning how loud it is at every tile
+// where it can be heard. Sound takes the loudest route to each tile, so a
+// noise can be heard through a thin wall, but travels further along corridors.
+pub fn propagate<F>(noise: Noise, is_wall: F) -> HashMap<Point<i16>, u8>
+    where F: Fn(Point<i16>) -> bool
+{
+    let mut heard = HashMap::new();
+    let mut open = BinaryHeap::new();
+
+    heard.insert(noise.source, noise.volume);
+    open.push((noise.volume, noise.source.x, noise.source.y));
+
+    while let Some((volume, x, y)) = open.pop() {
+        let pos = Point{x: x, y: y};
+        // A louder route to this tile has already been handled.
+        if heard.get(&pos) != Some(&volume) {
+            continue;
+        }
+
+        for &dir in NEIGHBOURS.iter() {
+            let next = pos + dir;
+            let attenuation = if is_wall(next) { WALL_ATTENUATION } else { FLOOR_ATTENUATION };
+
+            let next_volume = match volume.checked_sub(attenuation) {
+                Some(v) if v > 0 => v,
+                _ => continue,
+            };
+
+            if heard.get(&next).is_none_or(|&v| v < next_volume) {
+                heard.insert(next, next_volume);
+                open.push((next_volume, next.x, next.y));
+            }
+        }
+    }
+
+    heard
+}
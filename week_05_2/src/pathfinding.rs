@@ -0,0 +1,150 @@
+use tcod::pathfinding::AStar;
+
+use point::Point;
+
+// A walkability grid shared by every unit. The terrain is only updated when it
+// changes, and units update their own tile as they move, so nothing needs to
+// be rebuilt when a unit wants to find a path.
+pub struct PathGrid {
+    width: u8,
+    height: u8,
+    walkable: Vec<bool>,
+    // A count, rather than a flag, because nothing stops two blocking units
+    // from ending up on the same tile.
+    occupants: Vec<u8>,
+}
+
+impl PathGrid {
+    pub fn new(width: u8, height: u8) -> PathGrid {
+        let size = width as usize * height as usize;
+
+        PathGrid {
+            width: width,
+            height: height,
+            walkable: vec![false; size],
+            occupants: vec![0; size],
+        }
+    }
+
+    fn get_index(&self, Point{x, y}: Point<i16>) -> Option<usize> {
+        if x >= 0 && x < self.width as i16 && y >= 0 && y < self.height as i16 {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_walkable(&mut self, pos: Point<i16>, walkable: bool) {
+        if let Some(idx) = self.get_index(pos) {
+            self.walkable[idx] = walkable;
+        }
+    }
+
+    pub fn add_occupant(&mut self, pos: Point<i16>) {
+        if let Some(idx) = self.get_index(pos) {
+            self.occupants[idx] += 1;
+        }
+    }
+
+    pub fn remove_occupant(&mut self, pos: Point<i16>) {
+        if let Some(idx) = self.get_index(pos) {
+            self.occupants[idx] = self.occupants[idx].saturating_sub(1);
+        }
+    }
+
+    // Whether the terrain can be walked on, ignoring any units.
+    fn is_ground(&self, pos: Point<i16>) -> bool {
+        self.get_index(pos).is_some_and(|idx| self.walkable[idx])
+    }
+
+    pub fn is_walkable(&self, pos: Point<i16>) -> bool {
+        self.get_index(pos).is_some_and(|idx| self.walkable[idx] && self.occupants[idx] == 0)
+    }
+
+    // Returns the first step along the path, or None if there is no path, or it
+    // is longer than max_len.
+    pub fn find_step(&self, from: Point<i16>, to: Point<i16>, max_len: i32) -> Option<Point<i16>> {
+        self.find_step_within(from, to, max_len, |_| true)
+    }
+
+    // As find_step, but the path only goes through tiles that are allowed.
+    // The destination only needs to be walkable ground, as units often path
+    // towards another unit standing on it, but the step returned is never onto
+    // an occupied tile.
+    pub fn find_step_within<F>(&self, from: Point<i16>, to: Point<i16>, max_len: i32, allowed: F) -> Option<Point<i16>>
+        where F: Fn(Point<i16>) -> bool
+    {
+        let cost = |_: (i32, i32), (x, y): (i32, i32)| {
+            let pos = Point{x: x as i16, y: y as i16};
+            let walkable = if pos == to { self.is_ground(pos) } else { self.is_walkable(pos) };
+            if walkable && allowed(pos) { 1.0 } else { 0.0 }
+        };
+
+        let mut path = AStar::new_from_callback(self.width as i32, self.height as i32, cost, 0.0);
+        path.find((from.x as i32, from.y as i32), (to.x as i32, to.y as i32));
+
+        if path.is_empty() || path.len() >= max_len {
+            None
+        } else {
+            path.walk_one_step(true)
+                .map(|(x, y)| Point{x: x as i16, y: y as i16})
+                .filter(|&step| self.is_walkable(step))
+        }
+    }
+
+    // Returns the whole path, with the first step at the end so that it can
+    // be followed by popping steps off.
+    pub fn find_path(&self, from: Point<i16>, to: Point<i16>) -> Option<Vec<Point<i16>>> {
+        let cost = |_: (i32, i32), (x, y): (i32, i32)| {
+            if self.is_walkable(Point{x: x as i16, y: y as i16}) { 1.0 } else { 0.0 }
+        };
+
+        let mut path = AStar::new_from_callback(self.width as i32, self.height as i32, cost, 0.0);
+        path.find((from.x as i32, from.y as i32), (to.x as i32, to.y as i32));
+
+        if path.is_empty() {
+            None
+        } else {
+            let mut steps: Vec<_> = path.walk().map(|(x, y)| Point{x: x as i16, y: y as i16}).collect();
+            steps.reverse();
+            Some(steps)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use test::Bencher;
+
+    use super::*;
+    use map::{MAP_MAX_WIDTH, MAP_MAX_HEIGHT};
+
+    const MONSTER_COUNT: i16 = 120;
+    // The longest path monsters look for when closing in on the player.
+    const MAX_PATH_LEN: i32 = 40;
+
+    // Every monster on a large map looks for its next step towards the
+    // player, as happens each turn once they're all awake.
+    #[bench]
+    fn find_step_for_many_monsters(b: &mut Bencher) {
+        let mut grid = PathGrid::new(MAP_MAX_WIDTH, MAP_MAX_HEIGHT);
+
+        // Open ground with a pillar every few tiles, so paths have to bend.
+        for y in 1..MAP_MAX_HEIGHT as i16 - 1 {
+            for x in 1..MAP_MAX_WIDTH as i16 - 1 {
+                grid.set_walkable(Point{x: x, y: y}, x % 4 != 0 || y % 4 != 0);
+            }
+        }
+
+        let player = Point{x: 128, y: 130};
+        // Spread out around the player, on odd tiles so none are on a pillar.
+        let monsters: Vec<_> = (0..MONSTER_COUNT)
+            .map(|i| Point{x: 97 + (i % 30) * 2, y: 101 + (i / 30) * 16})
+            .collect();
+        for &pos in monsters.iter() {
+            grid.add_occupant(pos);
+        }
+
+        b.iter(|| monsters.iter().filter_map(|&m| grid.find_step(m, player, MAX_PATH_LEN)).count());
+    }
+}
@@ -27,7 +27,7 @@ impl Rectangle {
         }
     }
 
-    pub fn get_random_position(&self, rng: &mut rand::ThreadRng) -> Point<i16> {
+    pub fn get_random_position(&self, rng: &mut rand::StdRng) -> Point<i16> {
         Point {
             x: rng.gen_range(self.top_left.x+1, self.bottom_right.x),
             y: rng.gen_range(self.top_left.y+1, self.bottom_right.y),
@@ -39,6 +39,13 @@ impl Rectangle {
             && self.top_left.y <= other.bottom_right.y && self.bottom_right.y >= other.top_left.y
     }
 
+    // Treats the rectangle as half-open, so the bottom and right edges are
+    // not included. This matches how the view port is sized.
+    pub fn contains_point(&self, Point{x, y}: Point<i16>) -> bool {
+        x >= self.top_left.x && x < self.bottom_right.x
+            && y >= self.top_left.y && y < self.bottom_right.y
+    }
+
     pub fn clamp_to(&mut self, (left, top): (i16, i16), (right, bottom): (i16, i16)) {
         if self.top_left.x < left {
             let diff = left - self.top_left.x;
@@ -0,0 +1,70 @@
+use serde_yaml;
+
+use unit_type::UnitTypeLists;
+
+use std::fs::File;
+use std::path::Path;
+
+const ERR_SPAWN_LOAD: &str = "Unable to load spawn data.";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DepthStep {
+    pub depth: u8,
+    pub value: u32,
+}
+
+// A value that changes with dungeon depth. Each step sets the value from its
+// depth down, until the next step takes over. Above the first step, the
+// value is 0.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DepthTable(Vec<DepthStep>);
+
+impl DepthTable {
+    pub fn get(&self, depth: u8) -> u32 {
+        self.0.iter()
+            .filter(|s| s.depth <= depth)
+            .max_by_key(|s| s.depth)
+            .map_or(0, |s| s.value)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpawnSettings {
+    // Rooms with monsters get between 0 and one less than this many.
+    pub room_max_monsters: DepthTable,
+    // The deepest level, which has no stairs down and is guarded by the boss.
+    pub final_depth: u8,
+    pub boss: String,
+}
+
+pub fn load_spawn_settings() -> SpawnSettings {
+    let path = Path::new("data").join("spawning.yaml");
+    let data_file = File::open(&path).expect(ERR_SPAWN_LOAD);
+    serde_yaml::from_reader(&data_file).expect(ERR_SPAWN_LOAD)
+}
+
+// Prints the chance of each unit type being picked at each depth, along with
+// the average number of picks in a room that has any. Pack leaders bring
+// their pack along, which doesn't count as a pick, so their pack sizes are
+// listed too.
+pub fn print_spawn_report(unit_types: &UnitTypeLists) {
+    println!("The final level is {}, guarded by {}.", unit_types.get_final_depth(), unit_types.get_boss().get_name());
+
+    for depth in 1..unit_types.get_final_depth() + 1 {
+        let max_monsters = unit_types.get_room_max_monsters(depth);
+        let expected = if max_monsters > 0 { (max_monsters - 1) as f64 / 2.0 } else { 0.0 };
+        println!("Depth {} (up to {} monsters or packs per room, {:.1} on average):", depth, max_monsters.saturating_sub(1), expected);
+
+        let weights = unit_types.get_spawn_weights(depth);
+        let total: u32 = weights.iter().map(|&(_, w)| w).sum();
+
+        for (unit_type, weight) in weights {
+            let unique = if unit_type.is_unique() { " (unique)" } else { "" };
+            let pack: Vec<String> = unit_type.get_pack().iter()
+                .map(|m| format!("{}-{} {}", m.min, m.max, m.name))
+                .collect();
+            let pack = if pack.is_empty() { String::new() } else { format!(", leads {}", pack.join(" and ")) };
+            println!("    {:<16} {:>5.1}%{}{}", unit_type.get_name(), weight as f64 * 100.0 / total as f64, unique, pack);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use tcod::colors::Color;
+
+use serde_yaml;
+
+use ui::MessageCategory;
+
+use std::fs::File;
+use std::path::Path;
+
+const ERR_THEME_LOAD: &str = "Unable to load theme data.";
+
+pub const DEFAULT_THEME: &str = "default";
+pub const COLOUR_BLIND_THEME: &str = "colour_blind";
+
+// The colours used for each category of message.
+#[derive(Debug, Deserialize)]
+struct ThemeRaw {
+    combat: [u8; 3],
+    damage_taken: [u8; 3],
+    loot: [u8; 3],
+    system: [u8; 3],
+    flavour: [u8; 3],
+}
+
+#[derive(Debug)]
+pub struct Theme {
+    combat: Color,
+    damage_taken: Color,
+    loot: Color,
+    system: Color,
+    flavour: Color,
+}
+
+impl Theme {
+    pub fn get_color(&self, category: MessageCategory) -> Color {
+        match category {
+            MessageCategory::Combat => self.combat,
+            MessageCategory::DamageTaken => self.damage_taken,
+            MessageCategory::Loot => self.loot,
+            MessageCategory::System => self.system,
+            MessageCategory::Flavour => self.flavour,
+        }
+    }
+}
+
+// Themes are kept in data/themes, with the name being the file name.
+pub fn load_theme(name: &str) -> Theme {
+    let path = Path::new("data").join("themes").join(format!("{}.yaml", name));
+    let data_file = File::open(&path).expect(ERR_THEME_LOAD);
+    let raw: ThemeRaw = serde_yaml::from_reader(&data_file).expect(ERR_THEME_LOAD);
+
+    let to_color = |c: [u8; 3]| Color::new(c[0], c[1], c[2]);
+
+    Theme {
+        combat: to_color(raw.combat),
+        damage_taken: to_color(raw.damage_taken),
+        loot: to_color(raw.loot),
+        system: to_color(raw.system),
+        flavour: to_color(raw.flavour),
+    }
+}
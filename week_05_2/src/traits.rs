@@ -18,9 +18,12 @@ pub trait Position {
 pub trait Renderable: Position {
     fn get_color(&self) -> Color;
     fn get_glyph(&self) -> char;
-    fn render<T: Console>(&self, cons: &mut T) {
+    // The offset is the map position of the console's top-left corner, so the
+    // console only needs to be as large as the area being drawn.
+    fn render<T: Console>(&self, cons: &mut T, offset: Point<i16>) {
+        let Point{x, y} = self.get_position() - offset;
         cons.set_default_foreground(self.get_color());
-        cons.put_char(self.get_x() as i32, self.get_y() as i32, self.get_glyph(), BackgroundFlag::None);
+        cons.put_char(x as i32, y as i32, self.get_glyph(), BackgroundFlag::None);
     }
 }
 
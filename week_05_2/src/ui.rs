@@ -6,10 +6,50 @@ use tcod::colors::Color;
 use textwrap::wrap;
 
 use point::Point;
+use theme::Theme;
 
+use std::cmp::min;
 use std::collections::VecDeque;
 
 const BAR_WIDTH: i16 = 20;
+// Old messages are dropped once there are more than this many.
+const MAX_HISTORY: usize = 1000;
+// Space between the edge of a menu box and its text.
+const MENU_PADDING: i32 = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageCategory {
+    Combat,
+    // Anything that hurts the player.
+    DamageTaken,
+    Loot,
+    System,
+    Flavour,
+}
+
+impl MessageCategory {
+    fn get_name(&self) -> &'static str {
+        match *self {
+            MessageCategory::Combat => "Combat",
+            MessageCategory::DamageTaken => "Damage Taken",
+            MessageCategory::Loot => "Loot",
+            MessageCategory::System => "System",
+            MessageCategory::Flavour => "Flavour",
+        }
+    }
+
+    // The history screen's filters go through the categories in this order.
+    fn next(category: Option<MessageCategory>) -> Option<MessageCategory> {
+        match category {
+            None => Some(MessageCategory::Combat),
+            Some(MessageCategory::Combat) => Some(MessageCategory::DamageTaken),
+            Some(MessageCategory::DamageTaken) => Some(MessageCategory::Loot),
+            Some(MessageCategory::Loot) => Some(MessageCategory::System),
+            Some(MessageCategory::System) => Some(MessageCategory::Flavour),
+            Some(MessageCategory::Flavour) => None,
+        }
+    }
+}
 
 pub struct UI {
     position: Point<i16>,
@@ -18,10 +58,19 @@ pub struct UI {
     panel: Offscreen,
     bar_hp: Bar,
     message_box: TextBox,
+    theme: Theme,
+    // Only messages in this category are shown on the history screen, if set.
+    history_filter: Option<MessageCategory>,
+    // How many lines the history screen is scrolled up from the bottom.
+    history_scroll: usize,
+    // The number of lines on the history screen, as of the last time it was drawn.
+    history_page: usize,
+    // Describes whatever the mouse is over, shown under the HP bar.
+    tooltip: Option<String>,
 }
 
 impl UI {
-    pub fn new(pos: Point<i16>, panel_width: i32, panel_height: i32, max_hp: i16) -> UI {
+    pub fn new(pos: Point<i16>, panel_width: i32, panel_height: i32, max_hp: i16, theme: Theme) -> UI {
         UI {
             position: pos,
             width: panel_width,
@@ -30,10 +79,15 @@ impl UI {
             bar_hp: Bar::new(Point{x: 0, y: 0}, BAR_WIDTH, "HP", max_hp, tcod::colors::DARKER_RED, tcod::colors::LIGHT_RED),
             message_box: TextBox {
                 position: Point{x: BAR_WIDTH, y: 0},
-                lines: VecDeque::new(),
+                messages: VecDeque::new(),
                 max_lines: panel_height as usize,
                 width: panel_width as usize - BAR_WIDTH as usize,
-            }
+            },
+            theme: theme,
+            history_filter: None,
+            history_scroll: 0,
+            history_page: 0,
+            tooltip: None,
         }
     }
 
@@ -41,42 +95,220 @@ impl UI {
         self.bar_hp.set_value(new_val);
     }
 
-    pub fn add_message(&mut self, message: &str, color: Color) {
-        self.message_box.add_message(message, color);
+    pub fn add_message(&mut self, message: &str, category: MessageCategory) {
+        self.message_box.add_message(message, category);
     }
 
+    // Every message still in the history, oldest first.
+    pub fn get_message_log(&self) -> Vec<String> {
+        self.message_box.messages.iter().map(|m| m.get_text()).collect()
+    }
+
+    pub fn set_tooltip(&mut self, tooltip: Option<String>) {
+        self.tooltip = tooltip;
+    }
+
+    // Messages already shown are recoloured too.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+
     pub fn render<T: Console>(&mut self, cons: &mut T) {
         self.panel.set_default_background(tcod::colors::BLACK);
         self.panel.clear();
         self.bar_hp.render(&mut self.panel);
-        self.message_box.render(&mut self.panel);
+        self.message_box.render(&mut self.panel, &self.theme);
+
+        if let Some(ref tooltip) = self.tooltip {
+            self.panel.set_default_foreground(tcod::colors::LIGHT_GREY);
+            let lines = wrap(tooltip, BAR_WIDTH as usize - 1);
+            for (i, line) in lines.iter().take(self.height as usize - 2).enumerate() {
+                self.panel.print_ex(0, i as i32 + 2, BackgroundFlag::None, TextAlignment::Left, line.as_ref());
+            }
+        }
 
         tcod::console::blit(&self.panel, (0,0), (self.width, self.height), cons, (self.position.x as i32, self.position.y as i32), 1.0, 1.0);
     }
+
+    // Positive values scroll back towards older messages.
+    pub fn scroll_history(&mut self, lines: i32) {
+        if lines < 0 {
+            self.history_scroll = self.history_scroll.saturating_sub(-lines as usize);
+        } else {
+            self.history_scroll += lines as usize;
+        }
+    }
+
+    pub fn scroll_history_page(&mut self, up: bool) {
+        let page = self.history_page as i32;
+        self.scroll_history(if up { page } else { -page });
+    }
+
+    // Jumps back to the newest messages.
+    pub fn reset_history_scroll(&mut self) {
+        self.history_scroll = 0;
+    }
+
+    pub fn cycle_history_filter(&mut self) {
+        self.history_filter = MessageCategory::next(self.history_filter);
+        self.history_scroll = 0;
+    }
+
+    // Draws the whole message history over the given console, with a title
+    // line at the top and the key help at the bottom.
+    pub fn render_history<T: Console>(&mut self, cons: &mut T) {
+        let width = cons.width();
+        let height = cons.height();
+        let page = (height as usize).saturating_sub(2);
+        let lines = self.message_box.get_lines(width as usize - 2, &self.theme, self.history_filter);
+
+        // The scroll is only clamped here, as this is where the page size is known.
+        self.history_page = page;
+        self.history_scroll = min(self.history_scroll, lines.len().saturating_sub(page));
+        let end = lines.len() - self.history_scroll;
+        let start = end.saturating_sub(page);
+
+        cons.set_default_background(tcod::colors::BLACK);
+        cons.clear();
+
+        cons.set_default_foreground(tcod::colors::WHITE);
+        let filter = self.history_filter.map_or("All", |c| c.get_name());
+        cons.print_ex(width / 2, 0, BackgroundFlag::None, TextAlignment::Center, format!("Message History: {} ({}-{} of {})", filter, start + 1, end, lines.len()));
+
+        for (i, &(ref line, col)) in lines[start..end].iter().enumerate() {
+            cons.set_default_foreground(col);
+            cons.print_ex(1, i as i32 + 1, BackgroundFlag::None, TextAlignment::Left, line);
+        }
+
+        cons.set_default_foreground(tcod::colors::GREY);
+        cons.print_ex(width / 2, height - 1, BackgroundFlag::None, TextAlignment::Center, "Up/Down: scroll   PgUp/PgDn: page   Tab: filter   Esc: close");
+    }
+}
+
+#[derive(Debug)]
+struct Message {
+    text: String,
+    category: MessageCategory,
+    // How many times in a row the message has been added.
+    count: u32,
+}
+
+impl Message {
+    fn get_text(&self) -> String {
+        if self.count > 1 {
+            format!("{} x{}", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+// Draws a full screen page of text, with a title at the top and the key
+// help at the bottom.
+pub fn render_text_screen<T: Console>(cons: &mut T, title: &str, lines: &[String], help: &str) {
+    let width = cons.width();
+    let height = cons.height();
+
+    cons.set_default_background(tcod::colors::BLACK);
+    cons.clear();
+
+    cons.set_default_foreground(tcod::colors::WHITE);
+    cons.print_ex(width / 2, 1, BackgroundFlag::None, TextAlignment::Center, title);
+
+    cons.set_default_foreground(tcod::colors::LIGHT_GREY);
+    for (i, line) in lines.iter().take(height as usize - 5).enumerate() {
+        cons.print_ex(2, i as i32 + 3, BackgroundFlag::None, TextAlignment::Left, line);
+    }
+
+    cons.set_default_foreground(tcod::colors::GREY);
+    cons.print_ex(width / 2, height - 1, BackgroundFlag::None, TextAlignment::Center, help);
+}
+
+// Draws a box in the middle of the console listing the options, with the
+// selected one highlighted.
+pub fn render_menu<T: Console>(cons: &mut T, title: &str, options: &[String], selected: usize) {
+    let longest = options.iter().map(|o| o.len()).chain(Some(title.len())).max().unwrap_or(0);
+    let width = longest as i32 + MENU_PADDING * 2;
+    let height = options.len() as i32 + MENU_PADDING * 2 + 1;
+    let x = (cons.width() - width) / 2;
+    let y = (cons.height() - height) / 2;
+
+    cons.set_default_background(tcod::colors::DARKEST_GREY);
+    cons.rect(x, y, width, height, true, BackgroundFlag::Set);
+
+    cons.set_default_foreground(tcod::colors::WHITE);
+    cons.print_ex(x + width / 2, y + 1, BackgroundFlag::None, TextAlignment::Center, title);
+
+    for (i, option) in options.iter().enumerate() {
+        let color = if i == selected { tcod::colors::YELLOW } else { tcod::colors::GREY };
+        let marker = if i == selected { "> " } else { "  " };
+        cons.set_default_foreground(color);
+        cons.print_ex(x + MENU_PADDING - 2, y + MENU_PADDING + 1 + i as i32, BackgroundFlag::None, TextAlignment::Left, format!("{}{}", marker, option));
+    }
 }
 
 pub struct TextBox {
     position: Point<i16>,
-    lines: VecDeque<(String, Color)>,
+    messages: VecDeque<Message>,
     max_lines: usize,
     width: usize,
 }
 
 impl TextBox {
-    fn add_message(&mut self, message: &str, color: Color) {
-        let lines = wrap(message, self.width);
-
-        for line in lines {
-            if self.lines.len() == self.max_lines {
-                self.lines.pop_front();
+    fn add_message(&mut self, message: &str, category: MessageCategory) {
+        // Repeats of the last message are counted instead of added again.
+        if let Some(last) = self.messages.back_mut() {
+            if last.text == message && last.category == category {
+                last.count += 1;
+                return;
             }
+        }
 
-            self.lines.push_back((line.into(), color));
+        if self.messages.len() == MAX_HISTORY {
+            self.messages.pop_front();
         }
+
+        self.messages.push_back(Message {
+            text: message.into(),
+            category: category,
+            count: 1,
+        });
     }
 
-    fn render<T: Console>(&self, cons: &mut T) {
-        for (i, &(ref line, col)) in self.lines.iter().enumerate() {
+    // Every message in the category, or every message if there's no filter,
+    // wrapped to the given width, oldest first.
+    fn get_lines(&self, width: usize, theme: &Theme, filter: Option<MessageCategory>) -> Vec<(String, Color)> {
+        let mut lines = vec![];
+        for message in self.messages.iter().filter(|m| filter.is_none_or(|c| c == m.category)) {
+            let color = theme.get_color(message.category);
+            for line in wrap(&message.get_text(), width) {
+                lines.push((line.into(), color));
+            }
+        }
+
+        lines
+    }
+
+    fn render<T: Console>(&self, cons: &mut T, theme: &Theme) {
+        // Only the newest messages that fit are wrapped.
+        let mut lines = VecDeque::new();
+        for message in self.messages.iter().rev() {
+            let color = theme.get_color(message.category);
+            for line in wrap(&message.get_text(), self.width).into_iter().rev() {
+                lines.push_front((line.into_owned(), color));
+            }
+
+            if lines.len() >= self.max_lines {
+                break;
+            }
+        }
+
+        while lines.len() > self.max_lines {
+            lines.pop_front();
+        }
+
+        for (i, &(ref line, col)) in lines.iter().enumerate() {
             cons.set_default_foreground(col);
             cons.print_ex(self.position.x as i32, self.position.y as i32 + i as i32, BackgroundFlag::None, TextAlignment::Left, line);
         }
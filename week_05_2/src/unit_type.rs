@@ -2,14 +2,88 @@ use tcod::colors::{Color};
 
 use rand;
 use rand::Rng;
-use rand::distributions::{Weighted};
 
 use serde_yaml;
 
+use faction;
+use spawn_table;
+use spawn_table::DepthTable;
+
+use std::collections::HashSet;
 use std::fs::File;
 use std::path::Path;
 
 const ERR_UNIT_LOAD: &str = "Unable to load unit type data.";
+const ERR_UNKNOWN_MINION: &str = "Summon ability has an unknown minion type.";
+const ERR_SUMMONER_WITHOUT_SUMMON: &str = "Summoner has no Summon ability.";
+const ERR_UNKNOWN_PACK_MEMBER: &str = "Pack has an unknown member type.";
+const ERR_BAD_PACK_SIZE: &str = "Pack member needs a min no more than its max, and a max below 255.";
+const ERR_UNKNOWN_FACTION: &str = "Unit type has an unknown faction.";
+const ERR_UNKNOWN_BOSS: &str = "The boss has an unknown unit type.";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AiKind {
+    #[default]
+    MeleeBrute,
+    RangedKiter { range: u8 },
+    Coward,
+    PackHunter,
+    StationaryTurret,
+    // Keeps its distance, letting its Summon ability do the fighting.
+    Summoner,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbilityEffect {
+    // An attack that also does damage over the following turns.
+    PoisonBite { damage: u8, turns: u8 },
+    // Takes health from the target and gives it to the user.
+    LifeDrain { amount: u8 },
+    Summon { minion: String },
+    // Splits off a copy of the user after it is hit, sharing its health.
+    SplitOnHit,
+    // Hurts everything in a cone towards the target.
+    Breath { damage: u8, range: u8 },
+    Regenerate { amount: u8 },
+    // Makes the nearest enemy NPC in range stumble around at random.
+    Confuse { turns: u8, range: u8 },
+    // Turns the nearest enemy NPC in range to the user's side for a while.
+    Charm { turns: u8, range: u8 },
+}
+
+// When a unit is allowed to use an ability, on top of its cooldown.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbilityCondition {
+    #[default]
+    Always,
+    // The target is next to the unit.
+    Adjacent,
+    // The target is in sight, but not next to the unit.
+    AtRange,
+    // The unit is below half health.
+    Hurt,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Ability {
+    pub effect: AbilityEffect,
+    // How many turns the unit has to wait before using the ability again.
+    #[serde(default)]
+    pub cooldown: u8,
+    #[serde(default)]
+    pub condition: AbilityCondition,
+}
+
+// Units that spawn alongside a pack leader.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PackMember {
+    pub name: String,
+    pub min: u8,
+    pub max: u8,
+}
 
 #[derive(Debug, PartialEq)]
 pub struct UnitType {
@@ -20,6 +94,17 @@ pub struct UnitType {
     max_hp: u8,
     defence: u8,
     attack: u8,
+    sight_radius: u8,
+    light: Option<(Color, u8)>,
+    ai: AiKind,
+    pack: Vec<PackMember>,
+    abilities: Vec<Ability>,
+    // Unique units only ever appear once in a game.
+    unique: bool,
+    is_player: bool,
+    faction: String,
+    // Filled in from the faction data once the unit types are loaded.
+    enemies: Vec<String>,
 }
 
 impl UnitType {
@@ -34,6 +119,15 @@ impl UnitType {
             max_hp: 30,
             defence: 3,
             attack: 5,
+            sight_radius: 10,
+            light: None,
+            ai: AiKind::MeleeBrute,
+            pack: vec![],
+            abilities: vec![],
+            unique: false,
+            is_player: true,
+            faction: faction::PLAYER_FACTION.into(),
+            enemies: vec![],
         }
     }
 
@@ -64,6 +158,45 @@ impl UnitType {
     pub fn get_is_blocking(&self) -> bool {
         self.is_blocking
     }
+
+    // A radius of 0 means the unit is blind.
+    pub fn get_sight_radius(&self) -> u8 {
+        self.sight_radius
+    }
+
+    pub fn get_ai(&self) -> &AiKind {
+        &self.ai
+    }
+
+    // If this isn't empty, the unit leads a pack made up of these units.
+    pub fn get_pack(&self) -> &[PackMember] {
+        &self.pack
+    }
+
+    pub fn get_abilities(&self) -> &[Ability] {
+        &self.abilities
+    }
+
+    pub fn is_player(&self) -> bool {
+        self.is_player
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn get_faction(&self) -> &str {
+        &self.faction
+    }
+
+    pub fn is_hostile_to(&self, faction: &str) -> bool {
+        self.enemies.iter().any(|f| f == faction)
+    }
+
+    // The color and radius of the light given off by the unit, if any.
+    pub fn get_light(&self) -> Option<(Color, u8)> {
+        self.light
+    }
 }
 
 impl<'a> From<&'a UnitTypeRaw> for UnitType {
@@ -76,6 +209,15 @@ impl<'a> From<&'a UnitTypeRaw> for UnitType {
             max_hp: raw.max_hp,
             defence: raw.defence,
             attack: raw.attack,
+            sight_radius: raw.sight_radius,
+            light: raw.light.as_ref().map(|l| (Color::new(l.color[0], l.color[1], l.color[2]), l.radius)),
+            ai: raw.ai.clone(),
+            pack: raw.pack.clone(),
+            abilities: raw.abilities.clone(),
+            unique: raw.unique,
+            is_player: false,
+            faction: raw.faction.clone(),
+            enemies: vec![],
         }
     }
 }
@@ -87,54 +229,137 @@ struct UnitTypeRaw {
     name: String,
     glyph: char,
     color: [u8; 3],
-    chance: u32,
+    // Units without a chance never appear on their own.
+    #[serde(default)]
+    chance: DepthTable,
     is_blocking: bool,
     max_hp: u8,
     defence: u8,
     attack: u8,
+    sight_radius: u8,
+    faction: String,
+    #[serde(default)]
+    light: Option<LightRaw>,
+    #[serde(default)]
+    ai: AiKind,
+    #[serde(default)]
+    pack: Vec<PackMember>,
+    #[serde(default)]
+    abilities: Vec<Ability>,
+    #[serde(default)]
+    unique: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LightRaw {
+    color: [u8; 3],
+    radius: u8,
 }
 
 pub struct UnitTypeLists {
     pub types: Vec<UnitType>,
-    weights: Vec<Weighted<usize>>,
-    max_weight: u32,
+    // The spawn weight of each type, by depth.
+    chances: Vec<DepthTable>,
+    room_max_monsters: DepthTable,
+    final_depth: u8,
+    boss: String,
 }
 
 impl UnitTypeLists {
-    pub fn get_random_type(&self, rng: &mut rand::ThreadRng) -> &UnitType {
-        let mut val = rng.gen_range(0, self.max_weight);
+    // Every type that can spawn at the given depth, with its weight.
+    pub fn get_spawn_weights(&self, depth: u8) -> Vec<(&UnitType, u32)> {
+        self.types.iter().zip(self.chances.iter())
+            .map(|(t, c)| (t, c.get(depth)))
+            .filter(|&(_, w)| w > 0)
+            .collect()
+    }
+
+    // Returns None if nothing can spawn at the given depth. Unique types
+    // that have already spawned are skipped.
+    pub fn get_random_type(&self, rng: &mut rand::StdRng, depth: u8, spawned_uniques: &HashSet<String>) -> Option<&UnitType> {
+        let weights: Vec<_> = self.get_spawn_weights(depth).into_iter()
+            .filter(|&(t, _)| !spawned_uniques.contains(t.get_name()))
+            .collect();
+        let total: u32 = weights.iter().map(|&(_, w)| w).sum();
+        if total == 0 {
+            return None;
+        }
 
-        for weight in self.weights.iter() {
-            if weight.weight > val {
-                return &self.types[weight.item];
+        let mut val = rng.gen_range(0, total);
+        for (unit_type, weight) in weights {
+            if weight > val {
+                return Some(unit_type);
             }
 
-            val -= weight.weight;
+            val -= weight;
         }
 
         unreachable!()
     }
+
+    pub fn get_room_max_monsters(&self, depth: u8) -> u32 {
+        self.room_max_monsters.get(depth)
+    }
+
+    pub fn get_final_depth(&self) -> u8 {
+        self.final_depth
+    }
+
+    pub fn get_boss(&self) -> &UnitType {
+        self.get_type_by_name(&self.boss).expect(ERR_UNKNOWN_BOSS)
+    }
+
+    pub fn get_type_by_name(&self, name: &str) -> Option<&UnitType> {
+        self.types.iter().find(|t| t.name == name)
+    }
 }
 
 pub fn load_unit_types() -> UnitTypeLists{
     let path = Path::new("data").join("unit_types.yaml");
     let data_file = File::open(&path).expect(ERR_UNIT_LOAD);
     let raw_units: Vec<UnitTypeRaw> = serde_yaml::from_reader(&data_file).expect(ERR_UNIT_LOAD);
+    let factions = faction::load_factions();
 
     let mut types = vec![];
-    let mut weights = vec![];
+    let mut chances = vec![];
 
-    let mut running_total = 0;
-    for (i, raw_unit) in raw_units.iter().enumerate() {
-        types.push(raw_unit.into());
-        running_total += raw_unit.chance;
-
-        weights.push( Weighted{ weight: raw_unit.chance, item: i });
+    for raw_unit in raw_units.iter() {
+        let mut unit_type: UnitType = raw_unit.into();
+        unit_type.enemies = factions.get_enemies(&raw_unit.faction).expect(ERR_UNKNOWN_FACTION).clone();
+        types.push(unit_type);
+        chances.push(raw_unit.chance.clone());
     }
 
-    UnitTypeLists {
+    let spawn_settings = spawn_table::load_spawn_settings();
+
+    let lists = UnitTypeLists {
         types: types,
-        weights: weights,
-        max_weight: running_total,
+        chances: chances,
+        room_max_monsters: spawn_settings.room_max_monsters,
+        final_depth: spawn_settings.final_depth,
+        boss: spawn_settings.boss,
+    };
+
+    lists.get_boss();
+
+    // Catch typos in the data file now, rather than when something gets summoned.
+    for unit_type in lists.types.iter() {
+        for ability in unit_type.abilities.iter() {
+            if let AbilityEffect::Summon{ref minion} = ability.effect {
+                lists.get_type_by_name(minion).expect(ERR_UNKNOWN_MINION);
+            }
+        }
+
+        if unit_type.ai == AiKind::Summoner {
+            assert!(unit_type.abilities.iter().any(|a| matches!(a.effect, AbilityEffect::Summon{..})), "{}", ERR_SUMMONER_WITHOUT_SUMMON);
+        }
+
+        for member in unit_type.pack.iter() {
+            lists.get_type_by_name(&member.name).expect(ERR_UNKNOWN_PACK_MEMBER);
+            // The spawner picks a number in min..max + 1.
+            assert!(member.min <= member.max && member.max < u8::MAX, "{}", ERR_BAD_PACK_SIZE);
+        }
     }
+
+    lists
 }
\ No newline at end of file
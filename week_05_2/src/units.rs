@@ -1,17 +1,40 @@
-use tcod;
 use tcod::colors::{Color};
-use tcod::pathfinding::AStar;
 
 use traits::{Renderable, Movable, Position};
 use Direction;
 use point::Point;
-use map;
 use map::Map;
 use unit_type::UnitType;
-use ui::UI;
+use ui::{MessageCategory, UI};
+use light::LightSource;
+use noise::Noise;
+use faction;
+use ai;
+use ai::{AiBehaviour, TurnContext};
+
+use rand;
+use rand::Rng;
 
 use std::cmp::min;
-use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Values for I/N chance of a sleeping unit waking when it sees the player.
+const WAKE_CHANCE_I: u32 = 1;
+const WAKE_CHANCE_N: u32 = 3;
+// Sleeping units need a loud noise to wake them.
+const WAKE_VOLUME: u8 = 5;
+// Reaching level N takes this times the sum of 1 to N-1 experience.
+const LEVEL_UP_XP: u32 = 20;
+// Every level adds this much attack, and every other level adds this much
+// defence.
+const LEVEL_ATTACK_BONUS: u8 = 1;
+const LEVEL_DEFENCE_BONUS: u8 = 1;
+
+const ERR_NO_BEHAVIOUR: &str = "Unit has no AI behaviour.";
+
+// Gives every unit its own id, so it can be told apart from others of the
+// same type.
+static NEXT_UNIT_ID: AtomicUsize = AtomicUsize::new(0);
 
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -21,20 +44,202 @@ pub enum AttackResult {
     NoEffect,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AiState {
+    Asleep,
+    // Heading towards a room.
+    Wandering(Point<i16>),
+    // Heading towards where the player was last seen.
+    Investigating(Point<i16>),
+    // Chasing the player, who was last seen at the given position.
+    Hunting(Point<i16>),
+    Fleeing,
+    // Allies keep close to the player until told to stay.
+    Following,
+    // Keeps to the given position, such as an ally told to stay, or a boss
+    // guarding its room.
+    Staying(Point<i16>),
+}
+
+// A temporary behaviour that has to wait until the unit's turn is over.
+#[derive(Debug)]
+enum PendingEffect<'a> {
+    Confuse(u8),
+    Charm(&'a UnitType, u8),
+}
+
+#[derive(Debug)]
 pub struct Unit<'a> {
+    id: usize,
     position: Point<i16>,
     unit_type: &'a UnitType,
     cur_hp: u8,
+    ai_state: AiState,
+    // The path the unit is following, with the next step at the end.
+    path: Vec<Point<i16>>,
+    // Empty while the behaviour is taking the unit's turn. Anything put here
+    // during the turn replaces the behaviour once the turn is over.
+    behaviour: Option<Box<dyn AiBehaviour<'a> + 'a>>,
+    // Effects that arrived during the unit's own turn, applied after it.
+    pending_effects: Vec<PendingEffect<'a>>,
+    // While charmed, the unit takes the side of the charmer's type.
+    charmer: Option<&'a UnitType>,
+    pack: Option<u32>,
+    is_pack_leader: bool,
+    // Once broken, the unit will run from the player on sight.
+    is_morale_broken: bool,
+    // Turns left before each of the unit type's abilities can be used again.
+    cooldowns: Vec<u8>,
+    poison_damage: u8,
+    poison_turns: u8,
+    // Whether the unit has taken damage since it last checked.
+    was_hit: bool,
+    // Only the player gains experience, but levels work the same for anyone.
+    xp: u32,
+    level: u8,
 }
 
 impl<'a> Unit<'a> {
     pub fn new(pos: Point<i16>, unit_type: &'a UnitType) -> Unit<'a> {
         let cur_hp = unit_type.get_max_hp();
         Unit {
+            id: NEXT_UNIT_ID.fetch_add(1, Ordering::Relaxed),
             position: pos,
             unit_type: unit_type,
             cur_hp: cur_hp,
+            ai_state: AiState::Asleep,
+            path: vec![],
+            behaviour: Some(ai::new_behaviour(unit_type.get_ai())),
+            pending_effects: vec![],
+            charmer: None,
+            pack: None,
+            is_pack_leader: false,
+            is_morale_broken: false,
+            cooldowns: vec![0; unit_type.get_abilities().len()],
+            poison_damage: 0,
+            poison_turns: 0,
+            was_hit: false,
+            xp: 0,
+            level: 1,
+        }
+    }
+
+    pub fn set_pack(&mut self, pack: u32, is_leader: bool) {
+        self.pack = Some(pack);
+        self.is_pack_leader = is_leader;
+    }
+
+    pub fn get_pack(&self) -> Option<u32> {
+        self.pack
+    }
+
+    pub fn is_pack_leader(&self) -> bool {
+        self.is_pack_leader
+    }
+
+    pub fn is_morale_broken(&self) -> bool {
+        self.is_morale_broken
+    }
+
+    pub fn break_morale(&mut self) {
+        self.is_morale_broken = true;
+    }
+
+    pub fn get_cooldown(&self, ability: usize) -> u8 {
+        self.cooldowns[ability]
+    }
+
+    pub fn set_cooldown(&mut self, ability: usize, turns: u8) {
+        self.cooldowns[ability] = turns;
+    }
+
+    // A stronger poison replaces a weaker one.
+    pub fn poison(&mut self, damage: u8, turns: u8) {
+        if damage >= self.poison_damage {
+            self.poison_damage = damage;
+            self.poison_turns = turns;
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison_turns > 0
+    }
+
+    // Returns the damage done by poison this turn, if any.
+    pub fn tick_poison(&mut self) -> u8 {
+        if self.poison_turns == 0 {
+            return 0;
+        }
+
+        self.poison_turns -= 1;
+        let damage = min(self.poison_damage, self.cur_hp);
+        self.cur_hp -= damage;
+        damage
+    }
+
+    // Returns whether the unit has been hit since the last call.
+    pub fn take_was_hit(&mut self) -> bool {
+        let was_hit = self.was_hit;
+        self.was_hit = false;
+        was_hit
+    }
+
+    pub fn get_ai_state(&self) -> AiState {
+        self.ai_state
+    }
+
+    pub fn set_ai_state(&mut self, state: AiState) {
+        self.ai_state = state;
+        self.path.clear();
+    }
+
+    pub fn set_behaviour(&mut self, behaviour: Box<dyn AiBehaviour<'a> + 'a>) {
+        self.behaviour = Some(behaviour);
+    }
+
+    // Replaces the current behaviour until the confusion wears off.
+    pub fn confuse(&mut self, turns: u8) {
+        match self.behaviour.take() {
+            Some(previous) => self.set_behaviour(Box::new(ai::Confused::new(turns, previous))),
+            // The behaviour is busy taking the unit's turn, so wait until
+            // it's back.
+            None => self.pending_effects.push(PendingEffect::Confuse(turns)),
+        }
+    }
+
+    // The unit fights for the charmer's side until the charm wears off.
+    pub fn charm(&mut self, charmer: &'a UnitType, turns: u8) {
+        match self.behaviour.take() {
+            Some(previous) => {
+                self.charmer = Some(charmer);
+                let state = self.ai_state;
+                self.set_behaviour(Box::new(ai::Charmed::new(turns, previous, state)));
+            },
+            None => self.pending_effects.push(PendingEffect::Charm(charmer, turns)),
+        }
+    }
+
+    // Called by the charm's behaviour as it wears off, with what the unit was
+    // doing before it was charmed.
+    pub fn end_charm(&mut self, state: AiState) {
+        self.charmer = None;
+        self.set_ai_state(state);
+    }
+
+    pub fn is_charmed(&self) -> bool {
+        self.charmer.is_some()
+    }
+
+    // The unit type whose faction the unit is fighting for.
+    fn get_side(&self) -> &'a UnitType {
+        self.charmer.unwrap_or(self.unit_type)
+    }
+
+    // Called when something gets the unit's attention, such as being attacked.
+    pub fn alert(&mut self, source: Point<i16>) {
+        match self.ai_state {
+            AiState::Hunting(_) | AiState::Fleeing | AiState::Following | AiState::Staying(_) => {},
+            _ => self.set_ai_state(AiState::Hunting(source)),
         }
     }
 
@@ -42,6 +247,10 @@ impl<'a> Unit<'a> {
         self.unit_type.get_is_blocking()
     }
 
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
     pub fn get_name(&self) -> &str {
         &self.unit_type.get_name()
     }
@@ -50,55 +259,204 @@ impl<'a> Unit<'a> {
         self.cur_hp
     }
 
-    fn get_step_towards(&mut self, map: &Map, npcs: &VecDeque<Unit<'a>>, target: Point<i16>) -> Point<i16> {
-        // We could (probably should) cache this, but with so few units in view 
-        // at any one time, we'll just re-calculate every turn.
-        let mut path_map = map.get_pathfinding_map();
+    pub fn get_attack(&self) -> u8 {
+        self.unit_type.get_attack().saturating_add((self.level - 1) * LEVEL_ATTACK_BONUS)
+    }
+
+    pub fn get_defence(&self) -> u8 {
+        self.unit_type.get_defence().saturating_add((self.level - 1) / 2 * LEVEL_DEFENCE_BONUS)
+    }
+
+    pub fn get_level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn get_xp(&self) -> u32 {
+        self.xp
+    }
+
+    // The total experience needed to reach the next level.
+    pub fn get_next_level_xp(&self) -> u32 {
+        let level = self.level as u32;
+        LEVEL_UP_XP * level * (level + 1) / 2
+    }
+
+    // Returns the number of levels gained.
+    pub fn gain_xp(&mut self, amount: u32) -> u8 {
+        self.xp += amount;
 
-        for npc in npcs.iter() {
-            let pos = npc.get_position();
-            path_map.set(pos.x as i32, pos.y as i32, true, !npc.is_blocking());
+        let mut gained = 0;
+        while self.xp >= self.get_next_level_xp() {
+            self.level += 1;
+            gained += 1;
         }
+        gained
+    }
 
-        let mut path = AStar::new_from_map(path_map, 0.0);
-        let cur_pos = self.get_position();
-        path.find((cur_pos.x as i32, cur_pos.y as i32), (target.x as i32, target.y as i32));
-
-        // Path length check is to stop the AI from walking way around the map.
-        println!("Is Empty: {}, Length: {}", path.is_empty(), path.len());
-        if !path.is_empty() && path.len() < 25 {
-            println!("AStar");
-            let (x, y) = path.walk_one_step(true).expect("Pathfinding failed.");
-            Point{ x: x as i16, y: y as i16}
+    // Status effects currently on the unit, for showing to the player.
+    pub fn get_status_effects(&self) -> Vec<String> {
+        let mut effects = vec![];
+        if self.is_poisoned() {
+            effects.push(format!("Poisoned ({} damage, {} turns)", self.poison_damage, self.poison_turns));
+        }
+        effects
+    }
+
+    // The category for messages about this unit getting hurt.
+    pub fn get_damage_category(&self) -> MessageCategory {
+        if self.unit_type.is_player() {
+            MessageCategory::DamageTaken
         } else {
-            // Old, bad, pathfinding as backup.
-            let delta = target - cur_pos;
-            let dist = delta.radius();
+            MessageCategory::Combat
+        }
+    }
 
-            let new_pos = Point {
-                x: (delta.x as f64 / dist).round() as i16,
-                y: (delta.y as f64 / dist).round() as i16,
-            };
-            new_pos + cur_pos
+    pub fn is_ally(&self) -> bool {
+        self.get_side().get_faction() == faction::PLAYER_FACTION
+    }
+
+    pub fn is_hostile_to(&self, other: &Unit) -> bool {
+        let (side, other_side) = (self.get_side(), other.get_side());
+        side.is_hostile_to(other_side.get_faction()) || other_side.is_hostile_to(side.get_faction())
+    }
+
+    pub fn get_unit_type(&self) -> &'a UnitType {
+        self.unit_type
+    }
+
+    pub fn get_light_source(&self) -> Option<LightSource> {
+        self.unit_type.get_light().map(|(color, radius)| LightSource::new(self.position, color, radius))
+    }
+
+    // Units at or below a quarter of their health will try to run away.
+    pub fn is_badly_hurt(&self) -> bool {
+        self.cur_hp as u16 * 4 <= self.unit_type.get_max_hp() as u16
+    }
+
+    // Units always notice things right next to them, even when blind.
+    pub fn can_see(&self, map: &Map, target: Point<i16>) -> bool {
+        (target - self.position).radius() < 2.0
+            || map.has_line_of_sight(self.position, target, self.unit_type.get_sight_radius())
+    }
+
+    pub fn get_step_towards_player(&self, map: &Map, player_pos: Point<i16>) -> Option<Point<i16>> {
+        let cur_pos = self.get_position();
+        let grid = map.get_path_grid();
+
+        // The player map is shared by every unit, so try that first. It ignores
+        // other units, so if they're in the way, look for a path around them.
+        // Path length limit is to stop the AI from walking way around the map.
+        map.get_step_towards_player(cur_pos)
+            .or_else(|| grid.find_step(cur_pos, player_pos, 25))
+    }
+
+    pub fn get_step_away_from_player(&self, map: &Map) -> Option<Point<i16>> {
+        map.get_step_away_from_player(self.get_position())
+    }
+
+    // Moves the unit, keeping the map's pathfinding grid up to date.
+    pub fn walk_to(&mut self, map: &mut Map, pos: Point<i16>) {
+        map.remove_occupant(self);
+        self.move_to(pos);
+        map.add_occupant(self);
+    }
+
+    // Follows the stored path, only finding a new one when it runs out or
+    // something gets in the way.
+    pub fn travel_towards(&mut self, map: &mut Map, dest: Point<i16>) {
+        if self.path.is_empty() {
+            match map.get_path_grid().find_path(self.position, dest) {
+                Some(path) => self.path = path,
+                None => {
+                    // There's no way there. Pretend we've arrived, so a new
+                    // destination is chosen.
+                    self.ai_state = AiState::Wandering(self.position);
+                    return;
+                }
+            }
+        }
+
+        match self.path.pop() {
+            Some(step) if map.get_path_grid().is_walkable(step) => self.walk_to(map, step),
+            _ => self.path.clear(),
+        }
+    }
+
+    pub fn update_ai_state(&mut self, map: &Map, ui: &mut UI, sees_player: bool, wants_to_flee: bool, player_pos: Point<i16>, heard: Option<Noise>) {
+        let mut rng = rand::thread_rng();
+
+        let new_state = match (self.ai_state, heard) {
+            (AiState::Asleep, _) if sees_player && rng.gen_range(0, WAKE_CHANCE_N) < WAKE_CHANCE_I => AiState::Hunting(player_pos),
+            (AiState::Asleep, Some(noise)) if noise.volume >= WAKE_VOLUME => AiState::Investigating(noise.source),
+            (AiState::Asleep, _) => AiState::Asleep,
+            _ if sees_player && wants_to_flee => AiState::Fleeing,
+            _ if sees_player => AiState::Hunting(player_pos),
+            (AiState::Hunting(last_seen), _) => AiState::Investigating(last_seen),
+            (AiState::Fleeing, _) => AiState::Wandering(map.get_random_room_centre(&mut rng)),
+            (AiState::Wandering(_), Some(noise)) | (AiState::Investigating(_), Some(noise)) => AiState::Investigating(noise.source),
+            (AiState::Wandering(dest), _) | (AiState::Investigating(dest), _) if dest == self.position => {
+                AiState::Wandering(map.get_random_room_centre(&mut rng))
+            },
+            (state, _) => state,
+        };
+
+        if map.can_see_unit(self.position, player_pos) {
+            match (self.ai_state, new_state) {
+                (AiState::Hunting(_), _) | (AiState::Fleeing, _) => {},
+                (_, AiState::Hunting(_)) => ui.add_message(&format!("{} notices you!", self.get_name()), MessageCategory::Combat),
+                (_, AiState::Fleeing) => ui.add_message(&format!("{} flees!", self.get_name()), MessageCategory::Combat),
+                (AiState::Asleep, AiState::Investigating(_)) => ui.add_message(&format!("{} wakes up.", self.get_name()), MessageCategory::Flavour),
+                _ => {},
+            }
+        }
+
+        // Hunting updates every turn as the player moves, but doesn't use the path.
+        match (self.ai_state, new_state) {
+            (AiState::Hunting(_), AiState::Hunting(_)) => self.ai_state = new_state,
+            (old, new) if old != new => self.set_ai_state(new),
+            _ => {},
         }
     }
 
-    pub fn take_turn(&mut self, map: &Map, ui: &mut UI, npcs: &VecDeque<Unit<'a>>, player: &mut Unit) {
-        if !map.point_in_fov(self.get_position()) {
+    pub fn take_turn<'b>(&mut self, ctx: &mut TurnContext<'a, 'b>) {
+        for cooldown in self.cooldowns.iter_mut() {
+            *cooldown = cooldown.saturating_sub(1);
+        }
+
+        let poison_damage = self.tick_poison();
+        if poison_damage > 0 && ctx.map.can_see_unit(self.position, ctx.player.get_position()) {
+            ctx.ui.add_message(&format!("{} suffers {} poison damage.", self.get_name(), poison_damage), MessageCategory::Combat);
+        }
+
+        if self.cur_hp == 0 {
             return;
         }
 
-        if (self.get_position() - player.get_position()).radius() >= 2.0 {
-            let new_pos = self.get_step_towards(map, &npcs, player.get_position());
-            if map.can_move_to(new_pos) == map::CanMoveResponse::Open {
-                self.move_to(new_pos);
+        // The behaviour has to be taken out of the unit so that it can be
+        // given the unit to control.
+        let mut behaviour = self.behaviour.take().expect(ERR_NO_BEHAVIOUR);
+        behaviour.take_turn(self, ctx);
+
+        // Only put the behaviour back if nothing replaced it during the turn.
+        if self.behaviour.is_none() {
+            self.behaviour = match behaviour.wear_off(self) {
+                Some(previous) => Some(previous),
+                None => Some(behaviour),
+            };
+        }
+
+        let pending: Vec<PendingEffect<'a>> = self.pending_effects.drain(..).collect();
+        for effect in pending {
+            match effect {
+                PendingEffect::Confuse(turns) => self.confuse(turns),
+                PendingEffect::Charm(charmer, turns) => self.charm(charmer, turns),
             }
-        } else if player.get_hp() > 0 {
-            self.attack(player, ui);
         }
     }
 
     pub fn take_damage(&mut self, damage: u8) -> AttackResult {
+        self.was_hit |= damage > 0;
+
         if let Some(new_hp) = self.cur_hp.checked_sub(damage) {
             self.cur_hp = new_hp;
             AttackResult::Alive
@@ -109,17 +467,40 @@ impl<'a> Unit<'a> {
     }
 
     pub fn attack(&self, target: &mut Unit, ui: &mut UI) -> AttackResult {
-        if let Some(damage) = self.unit_type.get_attack().checked_sub(target.unit_type.get_defence()) {
-            ui.add_message(&format!("{} attacks {} for {} damage.", self.get_name(), target.get_name(), damage), tcod::colors::YELLOW);
+        self.attack_with_verb(target, Some(ui), "attacks")
+    }
+
+    pub fn shoot(&self, target: &mut Unit, ui: &mut UI) -> AttackResult {
+        self.attack_with_verb(target, Some(ui), "shoots")
+    }
+
+    // Used for fights the player can't see, which aren't reported.
+    pub fn attack_unseen(&self, target: &mut Unit) -> AttackResult {
+        self.attack_with_verb(target, None, "attacks")
+    }
+
+    pub fn shoot_unseen(&self, target: &mut Unit) -> AttackResult {
+        self.attack_with_verb(target, None, "shoots")
+    }
+
+    pub fn attack_with_verb(&self, target: &mut Unit, mut ui: Option<&mut UI>, verb: &str) -> AttackResult {
+        target.alert(self.position);
+
+        if let Some(damage) = self.get_attack().checked_sub(target.get_defence()) {
+            if let Some(ref mut ui) = ui {
+                ui.add_message(&format!("{} {} {} for {} damage.", self.get_name(), verb, target.get_name(), damage), target.get_damage_category());
+            }
             target.take_damage(damage)
         } else {
-            ui.add_message(&format!("{} attacks {}, but it has no effect!", self.get_name(), target.get_name()), tcod::colors::WHITE);
+            if let Some(ref mut ui) = ui {
+                ui.add_message(&format!("{} {} {}, but it has no effect!", self.get_name(), verb, target.get_name()), MessageCategory::Combat);
+            }
             AttackResult::NoEffect
         }
     }
 
     pub fn heal(&mut self, amount: u8) {
-        self.cur_hp = min(amount, self.unit_type.get_max_hp());
+        self.cur_hp = min(self.cur_hp.saturating_add(amount), self.unit_type.get_max_hp());
     }
 }
 
@@ -150,4 +531,33 @@ impl<'a> Movable for Unit<'a> {
     fn nudge(&mut self, dir: Direction){
         self.position = self.position + dir.to_rel_point();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unit_type;
+
+    #[test]
+    fn charmed_units_change_sides_until_the_charm_ends() {
+        let unit_types = unit_type::load_unit_types();
+        let player_type = UnitType::new("Player", '@', tcod::colors::WHITE);
+        let player = Unit::new(Point{x: 0, y: 0}, &player_type);
+        let mut dog = Unit::new(Point{x: 1, y: 0}, unit_types.get_type_by_name("Dog").unwrap());
+        let witch_type = unit_types.get_type_by_name("The Pale Witch").unwrap();
+        let witch = Unit::new(Point{x: 2, y: 0}, witch_type);
+
+        dog.set_ai_state(AiState::Following);
+        dog.charm(witch_type, 5);
+        assert!(dog.is_charmed());
+        assert!(!dog.is_ally());
+        assert!(dog.is_hostile_to(&player));
+        assert!(!dog.is_hostile_to(&witch));
+
+        dog.end_charm(AiState::Following);
+        assert!(dog.is_ally());
+        assert!(!dog.is_hostile_to(&player));
+        assert!(dog.is_hostile_to(&witch));
+        assert_eq!(dog.get_ai_state(), AiState::Following);
+    }
+}